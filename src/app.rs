@@ -1,30 +1,149 @@
-use std::{cell::RefCell, f32::consts::PI, rc::Rc};
-
-use glam::{Quat, Vec3};
-
-use crate::state::{Camera, CameraProjection, CameraView, Node, Shape, State, Topology, Transform};
+use std::{error, f32::consts::PI, fmt, sync::Arc};
+
+use glam::{Quat, Vec2, Vec3, Vec4};
+
+use crate::{
+    color::Color,
+    error::Error,
+    image::Image,
+    input::Input,
+    line::View,
+    path,
+    state::{
+        ArcMode, Camera, CameraProjection, CameraView, InstanceData, Light, LineCap, LineJoin,
+        LineStyle, LineWidth, Node, NodeId, SceneError, Shape, ShapeError, ShapeId, State, Stroke,
+        TextureId, Topology, Transform,
+    },
+    time::Time,
+};
 
 pub struct App {
-    prev_node: Rc<RefCell<Node>>,
-    curr_node: Rc<RefCell<Node>>,
+    /// Nodes opened by `push`, the root is always at the bottom.
+    stack: Vec<NodeId>,
     pub(crate) state: State,
+    /// Keeps the scene between frames instead of clearing it before each `update`
+    pub(crate) retained: bool,
+    /// Style given to the lines added from now on
+    line_style: LineStyle,
+    /// Set by `on_device_error`, taken by `run` to install on the device
+    pub(crate) device_error: Option<DeviceErrorHandler>,
     pub frame: u32,
     pub size: Size,
+    pub time: Time,
+    pub input: Input,
 }
 
+type DeviceErrorHandler = Box<dyn Fn(wgpu::Error) + Send + Sync>;
+
 pub struct Size {
     pub width: u32,
     pub height: u32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackError {
+    /// `pop` was called without a matching `push`.
+    Underflow,
+    /// A frame or scope ended with `open` pushes that were never popped.
+    Unbalanced { open: usize },
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackError::Underflow => write!(f, "pop called without a matching push"),
+            StackError::Unbalanced { open } => {
+                write!(f, "{open} push call(s) without a matching pop")
+            }
+        }
+    }
+}
+
+impl error::Error for StackError {}
+
+/// Vertices and their attributes, attributes apply to the last vertex added.
+#[derive(Default)]
 pub struct Mesh {
-    vertex: Vec<Vec3>,
+    pub(crate) vertex: Vec<Vec3>,
+    pub(crate) index: Vec<u32>,
+    pub(crate) normal: Vec<Vec3>,
+    pub(crate) uv: Vec<Vec2>,
+    pub(crate) color: Vec<Vec4>,
 }
 
 impl Mesh {
     pub fn vertex(&mut self, v: [f32; 3]) {
         self.vertex.push(v.into());
     }
+
+    /// Adds indexes into the vertices, the topology is then applied to the indexes instead of
+    /// the vertices in order, so vertices can be shared.
+    pub fn index(&mut self, ix: &[u32]) {
+        self.index.extend_from_slice(ix);
+    }
+
+    /// Sets the normal of the last vertex, vertices without one get a normal computed from
+    /// the triangles around them.
+    pub fn normal(&mut self, n: [f32; 3]) {
+        set_last(&mut self.normal, self.vertex.len(), Vec3::ZERO, n.into());
+    }
+
+    /// Sets the texture coordinates of the last vertex, vertices without them get `[0, 0]`.
+    pub fn uv(&mut self, uv: [f32; 2]) {
+        set_last(&mut self.uv, self.vertex.len(), Vec2::ZERO, uv.into());
+    }
+
+    /// Sets the color of the last vertex, multiplied with the node color. Vertices without
+    /// one are white.
+    pub fn color(&mut self, color: impl Into<Color>) {
+        set_last(
+            &mut self.color,
+            self.vertex.len(),
+            Vec4::ONE,
+            color.into().into(),
+        );
+    }
+
+    pub(crate) fn validate(&self, topology: Topology) -> Result<(), ShapeError> {
+        let vertices = self.vertex.len();
+        let count = if self.index.is_empty() {
+            vertices
+        } else {
+            self.index.len()
+        };
+
+        if count < topology.vertices() {
+            return Err(ShapeError::TooFewVertices { topology, count });
+        }
+
+        let list = matches!(topology, Topology::TriangleList | Topology::LineList);
+        if list && count % topology.vertices() != 0 {
+            return Err(ShapeError::VertexCount { topology, count });
+        }
+
+        if let Some(&index) = self.index.iter().find(|&&i| i as usize >= vertices) {
+            return Err(ShapeError::IndexOutOfRange { index, vertices });
+        }
+
+        let vertex = (0..vertices).find(|&i| {
+            !self.vertex[i].is_finite()
+                || self.normal.get(i).is_some_and(|n| !n.is_finite())
+                || self.uv.get(i).is_some_and(|uv| !uv.is_finite())
+                || self.color.get(i).is_some_and(|c| !c.is_finite())
+        });
+
+        match vertex {
+            Some(vertex) => Err(ShapeError::NonFinite { vertex }),
+            None => Ok(()),
+        }
+    }
+}
+
+fn set_last<T: Copy>(attribute: &mut Vec<T>, len: usize, default: T, value: T) {
+    attribute.resize(len, default);
+    if let Some(last) = attribute.last_mut() {
+        *last = value;
+    }
 }
 
 impl App {
@@ -46,17 +165,123 @@ impl App {
             },
         };
 
-        let state = State {
+        let mut state = State {
             camera,
+            clear: Color::WHITE,
+            viewport: Vec2::new(width as f32, height as f32),
             ..Default::default()
         };
+        state.clear_tree();
 
         Self {
             size: Size { width, height },
-            prev_node: state.root.clone(),
-            curr_node: state.root.clone(),
+            stack: vec![NodeId::ROOT],
             state,
+            retained: false,
+            line_style: LineStyle::default(),
+            device_error: None,
             frame: 0,
+            time: Time::new(),
+            input: Input::default(),
+        }
+    }
+
+    /// Zero sizes from a minimized window are ignored, keeping the last visible size.
+    pub(crate) fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        self.size.width = width;
+        self.size.height = height;
+        self.state.viewport = Vec2::new(width as f32, height as f32);
+
+        let ratio = self.aspect_ratio();
+        if let CameraProjection::Perspective { aspect_ratio, .. } =
+            &mut self.state.camera.projection
+        {
+            *aspect_ratio = ratio;
+        }
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        self.size.width as f32 / self.size.height as f32
+    }
+
+    /// Removes every node, shape and light from the scene, settings like the camera are kept.
+    ///
+    /// Node and shape handles from before are no longer valid.
+    pub fn clear(&mut self) {
+        self.state.clear_tree();
+        self.state.lights.clear();
+        self.stack = vec![NodeId::ROOT];
+    }
+
+    /// In retained mode the scene is kept between frames instead of being cleared before each
+    /// `update`, and is changed through the handles returned by `push` and the shape methods.
+    /// Frames where nothing changed reuse the serialized scene already on the GPU.
+    pub fn retained(&mut self, enabled: bool) {
+        self.retained = enabled;
+    }
+
+    /// Calls `handler` with errors the device reports outside of a call, a lost device
+    /// included, instead of panicking. Installed by [`run`](crate::run) before the next frame,
+    /// see `Context::on_device_error` when driving the renderer directly.
+    pub fn on_device_error(&mut self, handler: impl Fn(wgpu::Error) + Send + Sync + 'static) {
+        self.device_error = Some(Box::new(handler));
+    }
+
+    /// Checks that every `push` of the frame was matched by a `pop`.
+    pub fn end_frame(&self) -> Result<(), StackError> {
+        match self.stack.len() - 1 {
+            0 => Ok(()),
+            open => Err(StackError::Unbalanced { open }),
+        }
+    }
+
+    /// The mouse position projected onto the `z = 0` plane of the world.
+    pub fn mouse_world(&self) -> Vec3 {
+        let view = View::new(&self.state.camera, self.state.viewport);
+        view.pixels_to_world(&[self.input.mouse])[0]
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.state.camera
+    }
+
+    pub fn look_at(&mut self, eye: [f32; 3], center: [f32; 3], up: [f32; 3]) {
+        self.state.camera.view = CameraView {
+            eye: eye.into(),
+            center: center.into(),
+            up: up.into(),
+        }
+    }
+
+    /// Rotates the eye around the center, `yaw` around the up axis and `pitch` towards it.
+    /// The pitch stops short of the up axis so the view never flips.
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        let view = &mut self.state.camera.view;
+        let up = view.up.normalize();
+        let offset = view.eye - view.center;
+
+        let offset = Quat::from_axis_angle(up, yaw) * offset;
+
+        let current = offset.normalize().dot(up).clamp(-1., 1.).acos();
+        let limit = 0.01;
+        let pitch = pitch.clamp(current - PI + limit, current - limit);
+        let right = offset.cross(up).normalize();
+        let offset = Quat::from_axis_angle(right, pitch) * offset;
+
+        view.eye = view.center + offset;
+    }
+
+    /// Sets a perspective projection, the aspect ratio follows the window size.
+    pub fn perspective(&mut self, fov_y_radians: f32, z_near: f32, z_far: f32) {
+        self.state.camera.projection = CameraProjection::Perspective {
+            fov_y_radians,
+            aspect_ratio: self.aspect_ratio(),
+            z_near,
+            z_far,
         }
     }
 
@@ -79,58 +304,286 @@ impl App {
         }
     }
 
-    pub fn triangle(&mut self, a: [f32; 3], b: [f32; 3], c: [f32; 3]) {
-        let mut curr = self.curr_node.borrow_mut();
+    /// Sets the color the frame is cleared to before drawing, white by default.
+    pub fn background(&mut self, color: impl Into<Color>) {
+        self.state.clear = color.into();
+    }
+
+    /// Adds light coming from every direction. Adding any light turns on shading, scenes
+    /// without lights are drawn with their flat colors.
+    pub fn ambient_light(&mut self, color: impl Into<Color>) {
+        self.state.lights.push(Light::Ambient(color.into()));
+    }
+
+    /// Adds light shining along `direction`, like the sun.
+    pub fn directional_light(&mut self, direction: [f32; 3], color: impl Into<Color>) {
+        self.state.lights.push(Light::Directional {
+            direction: direction.into(),
+            color: color.into(),
+        });
+    }
+
+    /// Adds light shining from `position` in every direction, without falloff.
+    pub fn point_light(&mut self, position: [f32; 3], color: impl Into<Color>) {
+        self.state.lights.push(Light::Point {
+            position: position.into(),
+            color: color.into(),
+        });
+    }
+
+    /// Keeps `image` for the lifetime of the `App`, the returned handle can be passed to
+    /// [`App::texture`] on any frame.
+    pub fn load_texture(&mut self, image: Image) -> TextureId {
+        self.state.textures.push(Arc::new(image));
+        TextureId(self.state.textures.len() - 1)
+    }
+
+    /// Textures the shapes of the current node and its children, multiplied by their color.
+    pub fn texture(&mut self, texture: TextureId) {
+        self.update_current(|node| node.texture = Some(texture));
+    }
+
+    /// Fills the flat shapes and triangle meshes of the current node and its children with
+    /// `color`.
+    pub fn fill(&mut self, color: impl Into<Color>) {
+        let color = color.into();
+        self.update_current(|node| {
+            node.color = Some(color);
+            node.fill = Some(true);
+        });
+    }
+
+    /// Leaves the flat shapes and triangle meshes of the current node and its children
+    /// unfilled, only their outline is drawn.
+    pub fn no_fill(&mut self) {
+        self.update_current(|node| node.fill = Some(false));
+    }
+
+    /// Outlines the flat shapes and triangle meshes of the current node and its children in
+    /// `color`. Meshes are outlined along the edges that belong to a single triangle.
+    pub fn stroke(&mut self, color: impl Into<Color>) {
+        let stroke = Stroke::Color(color.into());
+        self.update_current(|node| node.stroke = Some(stroke));
+    }
+
+    /// Sets the width of the outlines of the current node and its children, one pixel by
+    /// default. Outlines are joined with miters.
+    pub fn stroke_weight(&mut self, width: LineWidth) {
+        self.update_current(|node| node.stroke_width = Some(width));
+    }
+
+    /// Turns off the outlines of the current node and its children, the default.
+    pub fn no_stroke(&mut self) {
+        self.update_current(|node| node.stroke = Some(Stroke::None));
+    }
+
+    fn update_current(&mut self, f: impl FnOnce(&mut Node)) {
+        // The current node can only be missing if it was removed while pushed
+        let node = *self.stack.last().unwrap();
+        let _ = self.state.update_node(node, f);
+    }
 
-        let triangle = Shape::Triangle(a.into(), b.into(), c.into());
+    /// Switches between depth-tested 3D drawing and painter's order 2D drawing, where shapes
+    /// are drawn over each other in the order they were added. Painter's order is the default.
+    ///
+    /// With depth testing all copies of a built-in shape are drawn together, in painter's order
+    /// only consecutive copies are.
+    pub fn depth_test(&mut self, enabled: bool) {
+        self.state.depth_test = enabled;
+        self.state.dirty = true;
+    }
 
-        self.state.shapes.push(triangle);
+    pub fn triangle(&mut self, a: [f32; 3], b: [f32; 3], c: [f32; 3]) -> ShapeId {
+        self.add(Shape::Triangle(a.into(), b.into(), c.into()))
+    }
 
-        curr.shapes.push(self.state.shapes.len() - 1);
+    pub fn square(&mut self) -> ShapeId {
+        self.add(Shape::Square)
     }
 
-    pub fn square(&mut self) {
-        let mut curr = self.curr_node.borrow_mut();
+    pub fn polygon(&mut self, n: u32) -> ShapeId {
+        self.add(Shape::Polygon(n))
+    }
 
-        let square = Shape::Square;
+    pub fn circle(&mut self) -> ShapeId {
+        self.add(Shape::Polygon(80))
+    }
 
-        self.state.shapes.push(square);
+    /// A cube with sides of length one.
+    pub fn cube(&mut self) -> ShapeId {
+        self.add(Shape::Cube)
+    }
 
-        curr.shapes.push(self.state.shapes.len() - 1);
+    /// A sphere of diameter one with `resolution` rings from pole to pole.
+    pub fn sphere(&mut self, resolution: u32) -> ShapeId {
+        self.add(Shape::Sphere(resolution))
     }
 
-    pub fn polygon(&mut self, n: u32) {
-        let mut curr = self.curr_node.borrow_mut();
+    /// A sphere of diameter one made from an icosahedron subdivided `subdivisions` times.
+    pub fn icosphere(&mut self, subdivisions: u32) -> ShapeId {
+        self.add(Shape::Icosphere(subdivisions))
+    }
+
+    /// A cylinder along the Y axis with diameter and height of one and `resolution` sides.
+    pub fn cylinder(&mut self, resolution: u32) -> ShapeId {
+        self.add(Shape::Cylinder(resolution))
+    }
+
+    /// A cone along the Y axis with diameter and height of one and `resolution` sides.
+    pub fn cone(&mut self, resolution: u32) -> ShapeId {
+        self.add(Shape::Cone(resolution))
+    }
 
-        self.state.shapes.push(Shape::Polygon(n));
+    /// A torus around the Z axis with a ring of diameter one and a tube of radius `thickness`.
+    pub fn torus(&mut self, resolution: u32, thickness: f32) -> ShapeId {
+        self.add(Shape::Torus(resolution, thickness))
+    }
 
-        curr.shapes.push(self.state.shapes.len() - 1);
+    /// A square on the XY plane with sides of length one, split into `resolution` by
+    /// `resolution` quads.
+    pub fn plane(&mut self, resolution: u32) -> ShapeId {
+        self.add(Shape::Plane(resolution))
     }
 
-    pub fn circle(&mut self) {
-        let mut curr = self.curr_node.borrow_mut();
+    /// Adds a mesh built by `f`. Invalid meshes are skipped when drawing, use
+    /// [`App::try_mesh`] to find out why.
+    pub fn mesh(&mut self, topology: Topology, f: impl Fn(&mut Mesh)) -> ShapeId {
+        let mut mesh = Mesh::default();
 
-        self.state.shapes.push(Shape::Polygon(80));
+        f(&mut mesh);
 
-        curr.shapes.push(self.state.shapes.len() - 1);
+        self.add(Shape::Mesh(mesh, topology))
     }
 
-    pub fn mesh(&mut self, topology: Topology, f: impl Fn(&mut Mesh) -> ()) {
-        let mut mesh = Mesh { vertex: vec![] };
+    /// Adds a mesh built by `f` only if it's valid.
+    pub fn try_mesh(
+        &mut self,
+        topology: Topology,
+        f: impl Fn(&mut Mesh),
+    ) -> Result<ShapeId, ShapeError> {
+        let mut mesh = Mesh::default();
 
         f(&mut mesh);
+        mesh.validate(topology)?;
+
+        Ok(self.add(Shape::Mesh(mesh, topology)))
+    }
+
+    /// Draws `shape` once for every item of `copies`, each placed relative to the current node.
+    ///
+    /// Copies go straight to the GPU as instances of a single draw, without a node each.
+    pub fn instances(
+        &mut self,
+        shape: Shape,
+        copies: impl IntoIterator<Item = InstanceData>,
+    ) -> ShapeId {
+        self.add(Shape::Instances(
+            Box::new(shape),
+            copies.into_iter().collect(),
+        ))
+    }
+
+    /// A rectangle with its top left corner at `x`, `y`, all in pixels.
+    ///
+    /// Shapes given in pixels are placed on the `z = 0` plane of the world where the pixels
+    /// appear on screen, and follow the camera and window size when they change. The transform
+    /// of the node still applies on top, so they are best drawn outside of `push`.
+    pub fn rect(&mut self, x: f32, y: f32, w: f32, h: f32) -> ShapeId {
+        self.add(Shape::Path(path::rect(x, y, w, h), true))
+    }
+
+    /// A rectangle in pixels like [`App::rect`] with its corners rounded by `radius`.
+    pub fn rounded_rect(&mut self, x: f32, y: f32, w: f32, h: f32, radius: f32) -> ShapeId {
+        self.add(Shape::Path(path::rounded_rect(x, y, w, h, radius), true))
+    }
+
+    /// An ellipse centered at `cx`, `cy` with radii `rx` and `ry`, all in pixels.
+    pub fn ellipse(&mut self, cx: f32, cy: f32, rx: f32, ry: f32) -> ShapeId {
+        let points = path::arc(Vec2::new(cx, cy), Vec2::new(rx, ry), 0., 2. * PI);
+        self.add(Shape::Path(points, true))
+    }
+
+    /// The part of an ellipse in pixels like [`App::ellipse`] from `start` to `stop`, in radians
+    /// growing clockwise from the right.
+    #[allow(clippy::too_many_arguments)]
+    pub fn arc(
+        &mut self,
+        cx: f32,
+        cy: f32,
+        rx: f32,
+        ry: f32,
+        start: f32,
+        stop: f32,
+        mode: ArcMode,
+    ) -> ShapeId {
+        let center = Vec2::new(cx, cy);
+        let mut points = path::arc(center, Vec2::new(rx, ry), start, stop);
+
+        let shape = match mode {
+            ArcMode::Open => Shape::Path(points, false),
+            ArcMode::Chord => Shape::Path(points, true),
+            ArcMode::Pie => {
+                points.insert(0, center);
+                Shape::Path(points, true)
+            }
+        };
+        self.add(shape)
+    }
+
+    /// A quadrilateral through four corners in pixels, in order around its edge.
+    pub fn quad(&mut self, a: [f32; 2], b: [f32; 2], c: [f32; 2], d: [f32; 2]) -> ShapeId {
+        let points = [a, b, c, d].map(Vec2::from).into();
+        self.add(Shape::Path(points, true))
+    }
+
+    /// A dot at `x`, `y` in pixels, as wide as the stroke and in its color, or in the fill
+    /// color without a stroke.
+    pub fn point(&mut self, x: f32, y: f32) -> ShapeId {
+        self.add(Shape::Point(Vec2::new(x, y)))
+    }
 
-        let mut curr = self.curr_node.borrow_mut();
+    /// A star centered at `cx`, `cy` with `points` tips at `outer` pixels from the center and
+    /// the corners between them at `inner` pixels, the first tip pointing up.
+    pub fn star(&mut self, cx: f32, cy: f32, inner: f32, outer: f32, points: u32) -> ShapeId {
+        let points = path::star(Vec2::new(cx, cy), inner, outer, points);
+        self.add(Shape::Path(points, true))
+    }
+
+    fn add(&mut self, shape: Shape) -> ShapeId {
+        self.state.add_shape(*self.stack.last().unwrap(), shape)
+    }
 
-        self.state.shapes.push(Shape::Mesh(mesh.vertex, topology));
+    /// Sets the width of the lines added from now on, one pixel by default.
+    pub fn line_width(&mut self, width: LineWidth) {
+        self.line_style.width = width;
+    }
 
-        curr.shapes.push(self.state.shapes.len() - 1);
+    /// Sets how the segments of the polylines added from now on are joined, miter by default.
+    pub fn line_join(&mut self, join: LineJoin) {
+        self.line_style.join = join;
     }
 
-    // pub fn line(&mut self, length: u32, width: u32) {
-    //     self.push(Some([width, length, 1.]), , )
+    /// Sets how the ends of the lines added from now on are drawn, butt by default.
+    pub fn line_cap(&mut self, cap: LineCap) {
+        self.line_style.cap = cap;
+    }
 
-    // }
+    /// A line from `a` to `b`, see [`App::polyline`].
+    pub fn line(&mut self, a: [f32; 3], b: [f32; 3]) -> ShapeId {
+        self.polyline(&[a, b], false)
+    }
+
+    /// A line through `points`, back to the first one when `closed`.
+    ///
+    /// Lines are drawn in the node color with the current width, join and cap, and aren't
+    /// affected by lights. They are rebuilt whenever the camera or window size changes.
+    pub fn polyline(&mut self, points: &[[f32; 3]], closed: bool) -> ShapeId {
+        self.add(Shape::Line(
+            points.iter().map(|&point| point.into()).collect(),
+            closed,
+            self.line_style,
+        ))
+    }
 
     pub fn push(
         &mut self,
@@ -138,7 +591,7 @@ impl App {
         rotation: Option<[f32; 4]>,
         translation: Option<[f32; 3]>,
         color: Option<[f64; 4]>,
-    ) {
+    ) -> NodeId {
         let mut transform = Transform::default();
 
         if let Some(scale) = scale {
@@ -151,23 +604,213 @@ impl App {
             transform.translation = Some(Vec3::from_array(translation));
         };
 
-        self.prev_node = self.curr_node.clone();
+        self.push_node(transform, color.map(|c| c.into()))
+    }
 
-        let mut prev = self.prev_node.borrow_mut();
+    /// Returns to the parent of the current node.
+    ///
+    /// # Panics
+    ///
+    /// Panics without a matching `push`, see [`App::try_pop`].
+    pub fn pop(&mut self) {
+        if let Err(error) = self.try_pop() {
+            panic!("{error}");
+        }
+    }
+
+    pub fn try_pop(&mut self) -> Result<(), StackError> {
+        if self.stack.len() == 1 {
+            return Err(StackError::Underflow);
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
 
-        let curr = Rc::new(RefCell::new(Node {
+    /// Pushes `transform`, calls `f` and pops again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` leaves its own pushes and pops unbalanced.
+    pub fn with<R>(&mut self, transform: Transform, f: impl FnOnce(&mut App) -> R) -> R {
+        self.push_node(transform, None);
+        let depth = self.stack.len();
+
+        let result = f(self);
+
+        match self.stack.len() {
+            len if len < depth => panic!("{}", StackError::Underflow),
+            len if len > depth => panic!("{}", StackError::Unbalanced { open: len - depth }),
+            _ => self.stack.pop(),
+        };
+
+        result
+    }
+
+    fn push_node(&mut self, transform: Transform, color: Option<Color>) -> NodeId {
+        let node = Node {
             transform: Some(transform),
-            color: color.map(|c| c.into()),
-            children: Vec::new(),
-            shapes: Vec::new(),
-        }));
+            color,
+            ..Default::default()
+        };
+        let id = self.state.add_node(*self.stack.last().unwrap(), node);
 
-        prev.children.push(curr.clone());
+        self.stack.push(id);
+        id
+    }
 
-        self.curr_node = curr.clone();
+    /// Replaces the transform of a node, relative to its parent like in `push`.
+    pub fn set_transform(&mut self, node: NodeId, transform: Transform) -> Result<(), SceneError> {
+        self.state
+            .update_instances(node, true, |node| node.transform = Some(transform))
     }
 
-    pub fn pop(&mut self) {
-        self.curr_node = self.prev_node.clone();
+    /// Replaces the color of a node, `None` inherits the parent color.
+    pub fn set_color(&mut self, node: NodeId, color: Option<[f64; 4]>) -> Result<(), SceneError> {
+        self.state
+            .update_instances(node, false, |node| node.color = color.map(|c| c.into()))
+    }
+
+    /// Hides or shows a node together with its children.
+    pub fn set_visible(&mut self, node: NodeId, visible: bool) -> Result<(), SceneError> {
+        self.state
+            .update_instances(node, false, |node| node.hidden = !visible)
+    }
+
+    /// Moves a node and its children under `parent`, keeping its transform relative to the
+    /// new parent.
+    pub fn reparent(&mut self, node: NodeId, parent: NodeId) -> Result<(), SceneError> {
+        self.state.reparent(node, parent)
+    }
+
+    /// Removes a node together with its children and their shapes.
+    pub fn remove(&mut self, node: NodeId) -> Result<(), SceneError> {
+        self.state.remove(node)
+    }
+
+    pub fn remove_shape(&mut self, shape: ShapeId) -> Result<(), SceneError> {
+        self.state.remove_shape(shape)
+    }
+
+    /// Checks a shape the way drawing does. Invalid shapes, like a polygon with fewer than three
+    /// sides or a triangle with a NaN corner, are skipped when drawing without an error.
+    pub fn validate(&self, shape: ShapeId) -> Result<(), Error> {
+        self.state.shape(shape)?.validate()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh(vertices: &[[f32; 3]], indexes: &[u32]) -> Mesh {
+        let mut mesh = Mesh::default();
+        for &v in vertices {
+            mesh.vertex(v);
+        }
+        mesh.index(indexes);
+        mesh
+    }
+
+    const CORNERS: [[f32; 3]; 3] = [[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]];
+
+    #[test]
+    fn strip_needs_a_full_triangle() {
+        let mesh = mesh(&CORNERS[..2], &[]);
+
+        assert_eq!(
+            mesh.validate(Topology::TriangleStrip),
+            Err(ShapeError::TooFewVertices {
+                topology: Topology::TriangleStrip,
+                count: 2
+            })
+        );
+    }
+
+    #[test]
+    fn strip_counts_indexes_when_given() {
+        let mesh = mesh(&CORNERS, &[0, 1]);
+
+        assert_eq!(
+            mesh.validate(Topology::TriangleStrip),
+            Err(ShapeError::TooFewVertices {
+                topology: Topology::TriangleStrip,
+                count: 2
+            })
+        );
+        assert_eq!(mesh.validate(Topology::LineStrip), Ok(()));
+    }
+
+    #[test]
+    fn list_needs_whole_primitives() {
+        let mesh = mesh(&[CORNERS[0], CORNERS[1], CORNERS[2], CORNERS[0]], &[]);
+
+        assert_eq!(
+            mesh.validate(Topology::TriangleList),
+            Err(ShapeError::VertexCount {
+                topology: Topology::TriangleList,
+                count: 4
+            })
+        );
+        assert_eq!(mesh.validate(Topology::TriangleStrip), Ok(()));
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let mesh = mesh(&CORNERS, &[0, 1, 3]);
+
+        assert_eq!(
+            mesh.validate(Topology::TriangleList),
+            Err(ShapeError::IndexOutOfRange {
+                index: 3,
+                vertices: 3
+            })
+        );
+    }
+
+    #[test]
+    fn non_finite_values() {
+        let position = mesh(&[CORNERS[0], [f32::NAN, 0., 0.], CORNERS[2]], &[]);
+        assert_eq!(
+            position.validate(Topology::TriangleList),
+            Err(ShapeError::NonFinite { vertex: 1 })
+        );
+
+        let mut normal = mesh(&CORNERS, &[]);
+        normal.normal([0., 0., f32::INFINITY]);
+        assert_eq!(
+            normal.validate(Topology::TriangleList),
+            Err(ShapeError::NonFinite { vertex: 2 })
+        );
+    }
+
+    #[test]
+    fn validate_reports_invalid_built_in_shapes() {
+        let mut app = App::new(100, 100);
+        let polygon = app.polygon(2);
+        let triangle = app.triangle([0., 0., 0.], [1., f32::NAN, 0.], [0., 1., 0.]);
+        let torus = app.torus(8, f32::NAN);
+        let square = app.square();
+
+        assert!(matches!(
+            app.validate(polygon),
+            Err(Error::Shape(ShapeError::Polygon { sides: 2 }))
+        ));
+        assert!(matches!(
+            app.validate(triangle),
+            Err(Error::Shape(ShapeError::NonFinite { vertex: 1 }))
+        ));
+        assert!(matches!(
+            app.validate(torus),
+            Err(Error::Shape(ShapeError::Thickness))
+        ));
+        assert!(app.validate(square).is_ok());
+
+        app.remove_shape(square).unwrap();
+        assert!(matches!(
+            app.validate(square),
+            Err(Error::Scene(SceneError::UnknownShape(_)))
+        ));
     }
 }
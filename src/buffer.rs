@@ -0,0 +1,55 @@
+//! GPU buffers kept across frames.
+
+use wgpu::{Buffer, BufferDescriptor, BufferSlice, BufferUsages, Device, Queue};
+
+/// Smallest allocation, so small scenes don't reallocate while they grow.
+const MIN_SIZE: u64 = 4096;
+
+/// A buffer rewritten every frame, reallocated only when the data outgrows it.
+pub(crate) struct GrowableBuffer {
+    buffer: Buffer,
+    usage: BufferUsages,
+}
+
+impl GrowableBuffer {
+    pub(crate) fn new(device: &Device, usage: BufferUsages) -> Self {
+        let usage = usage | BufferUsages::COPY_DST;
+
+        GrowableBuffer {
+            buffer: allocate(device, usage, MIN_SIZE),
+            usage,
+        }
+    }
+
+    /// Replaces the start of the buffer with `bytes`, growing it to the next power of two when
+    /// they don't fit.
+    pub(crate) fn write(&mut self, device: &Device, queue: &Queue, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        let size = bytes.len() as u64;
+        if size > self.buffer.size() {
+            self.buffer = allocate(device, self.usage, size.next_power_of_two());
+        }
+
+        queue.write_buffer(&self.buffer, 0, bytes);
+    }
+
+    pub(crate) fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub(crate) fn slice(&self, offset: u64) -> BufferSlice<'_> {
+        self.buffer.slice(offset..)
+    }
+}
+
+fn allocate(device: &Device, usage: BufferUsages, size: u64) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: None,
+        size,
+        usage,
+        mapped_at_creation: false,
+    })
+}
@@ -9,7 +9,11 @@ pub struct Color {
 }
 
 impl Color {
-    pub fn new(r: f64, g: f64, b: f64, a: f64) -> Self {
+    pub const WHITE: Color = Color::new(1., 1., 1., 1.);
+    pub const BLACK: Color = Color::new(0., 0., 0., 1.);
+    pub const TRANSPARENT: Color = Color::new(0., 0., 0., 0.);
+
+    pub const fn new(r: f64, g: f64, b: f64, a: f64) -> Self {
         Color { r, g, b, a }
     }
 }
@@ -1,38 +1,67 @@
+use std::sync::mpsc;
+
 use wgpu::{
-    Device, DeviceDescriptor, Extent3d, Features, Instance, Limits, PowerPreference, PresentMode,
-    Queue, RequestAdapterOptions, Surface, SurfaceConfiguration, TextureDescriptor,
+    Adapter, BufferAsyncError, BufferDescriptor, BufferUsages, CommandEncoder, Device,
+    DeviceDescriptor, Extent3d, Features, ImageCopyBuffer, ImageDataLayout, Instance, Limits,
+    Maintain, MapMode, PowerPreference, PresentMode, Queue, RequestAdapterOptions, Surface,
+    SurfaceConfiguration, SurfaceError, SurfaceTexture, Texture, TextureDescriptor,
     TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
+    COPY_BYTES_PER_ROW_ALIGNMENT,
 };
 use winit::{dpi::PhysicalSize, window::Window};
 
+use crate::{color::Color, error::Error, image::Image};
+
 pub struct Context {
-    pub(crate) window: Window,
-    pub(crate) surface: Surface,
+    pub(crate) target: Target,
     pub(crate) surface_config: SurfaceConfiguration,
     pub(crate) device: Device,
     pub(crate) queue: Queue,
     pub(crate) texture_format: TextureFormat,
     pub(crate) multisample_texture_view: TextureView,
+    pub(crate) depth_texture_view: TextureView,
+    pub(crate) clear: Option<Color>,
+    /// Set while the window is minimized, nothing is rendered until it gets a size again
+    pub(crate) paused: bool,
+}
+
+pub(crate) const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+/// Where the resolved frame ends up: a window swapchain or an offscreen texture.
+pub(crate) enum Target {
+    Window { window: Window, surface: Surface },
+    Offscreen { texture: Texture },
+}
+
+/// The texture a single frame is resolved into.
+pub(crate) struct Frame {
+    pub(crate) view: TextureView,
+    surface_texture: Option<SurfaceTexture>,
+}
+
+impl Frame {
+    pub(crate) fn present(self) {
+        if let Some(surface_texture) = self.surface_texture {
+            surface_texture.present();
+        }
+    }
 }
 
 impl Context {
-    pub async fn new(window: Window) -> Self {
+    pub async fn new(window: Window) -> Result<Self, Error> {
         let instance = Instance::default();
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+        let surface = unsafe { instance.create_surface(&window) }?;
 
         let options = RequestAdapterOptions {
             power_preference: PowerPreference::HighPerformance,
             force_fallback_adapter: false,
             compatible_surface: Some(&surface),
         };
-        let adapter = instance.request_adapter(&options).await.unwrap();
-
-        let descriptor = DeviceDescriptor {
-            label: None,
-            features: Features::MULTI_DRAW_INDIRECT,
-            limits: Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits()),
-        };
-        let (device, queue) = adapter.request_device(&descriptor, None).await.unwrap();
+        let adapter = instance
+            .request_adapter(&options)
+            .await
+            .ok_or(Error::NoAdapter)?;
+        let (device, queue) = request_device(&adapter).await?;
 
         //Swapchain
         let surface_capabilities = surface.get_capabilities(&adapter);
@@ -50,27 +79,272 @@ impl Context {
         surface.configure(&device, &surface_config);
 
         let multisample_texture_view = create_multisample_texture_view(&device, &surface_config);
+        let depth_texture_view = create_depth_texture_view(&device, &surface_config);
 
-        Context {
-            window,
-            surface,
+        Ok(Context {
+            target: Target::Window { window, surface },
             surface_config,
             device,
             queue,
             texture_format,
             multisample_texture_view,
-        }
+            depth_texture_view,
+            clear: None,
+            paused: false,
+        })
+    }
+
+    /// Creates a context without a window that renders into an offscreen texture.
+    ///
+    /// Set `force_fallback_adapter` to use the software adapter on machines without a GPU.
+    pub async fn headless(
+        width: u32,
+        height: u32,
+        force_fallback_adapter: bool,
+    ) -> Result<Self, Error> {
+        let instance = Instance::default();
+
+        let options = RequestAdapterOptions {
+            power_preference: PowerPreference::HighPerformance,
+            force_fallback_adapter,
+            compatible_surface: None,
+        };
+        let adapter = instance
+            .request_adapter(&options)
+            .await
+            .ok_or(Error::NoAdapter)?;
+        let (device, queue) = request_device(&adapter).await?;
+
+        // The surface configuration only describes the offscreen texture here
+        let texture_format = TextureFormat::Rgba8UnormSrgb;
+        let surface_config = SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: texture_format,
+            width,
+            height,
+            present_mode: PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![texture_format],
+        };
+
+        let texture = create_offscreen_texture(&device, &surface_config);
+        let multisample_texture_view = create_multisample_texture_view(&device, &surface_config);
+        let depth_texture_view = create_depth_texture_view(&device, &surface_config);
+
+        Ok(Context {
+            target: Target::Offscreen { texture },
+            surface_config,
+            device,
+            queue,
+            texture_format,
+            multisample_texture_view,
+            depth_texture_view,
+            clear: None,
+            paused: false,
+        })
     }
 
+    /// Overrides the background of every scene rendered with this context, `None` restores the
+    /// scene background. Offscreen targets keep the alpha channel, so [`Color::TRANSPARENT`]
+    /// produces images ready for compositing.
+    pub fn set_clear(&mut self, clear: Option<Color>) {
+        self.clear = clear;
+    }
+
+    /// Resizes the render targets. A zero-sized window, usually minimized, pauses rendering
+    /// until the next resize.
     pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        if size.width == 0 || size.height == 0 {
+            self.paused = true;
+            return;
+        }
+        let resumed = std::mem::replace(&mut self.paused, false);
+
         self.surface_config.width = size.width;
         self.surface_config.height = size.height;
-        self.surface.configure(&self.device, &self.surface_config);
+        match &mut self.target {
+            Target::Window { surface, .. } => surface.configure(&self.device, &self.surface_config),
+            Target::Offscreen { texture } => {
+                *texture = create_offscreen_texture(&self.device, &self.surface_config)
+            }
+        }
         self.multisample_texture_view =
             create_multisample_texture_view(&self.device, &self.surface_config);
+        self.depth_texture_view = create_depth_texture_view(&self.device, &self.surface_config);
+
+        // Rendering stops requesting redraws while paused
+        if resumed {
+            self.request_redraw();
+        }
+    }
+
+    /// Calls `handler` with errors the device reports outside of a call, a lost device
+    /// included, instead of panicking.
+    pub fn on_device_error(&self, handler: impl Fn(wgpu::Error) + Send + 'static) {
+        self.device.on_uncaptured_error(Box::new(handler));
+    }
+
+    /// The texture to render the next frame into, `None` when the frame should be skipped.
+    ///
+    /// Lost and outdated surfaces are reconfigured and the frame skipped, the next one renders
+    /// normally. Only running out of memory is an error.
+    pub(crate) fn frame(&self) -> Result<Option<Frame>, Error> {
+        if self.paused {
+            return Ok(None);
+        }
+
+        match &self.target {
+            Target::Window { surface, .. } => {
+                let surface_texture = match surface.get_current_texture() {
+                    Ok(surface_texture) => surface_texture,
+                    Err(SurfaceError::Lost | SurfaceError::Outdated) => {
+                        surface.configure(&self.device, &self.surface_config);
+                        return Ok(None);
+                    }
+                    Err(SurfaceError::Timeout) => return Ok(None),
+                    Err(error) => return Err(error.into()),
+                };
+                let view = surface_texture
+                    .texture
+                    .create_view(&TextureViewDescriptor::default());
+
+                Ok(Some(Frame {
+                    view,
+                    surface_texture: Some(surface_texture),
+                }))
+            }
+            Target::Offscreen { texture } => Ok(Some(Frame {
+                view: texture.create_view(&TextureViewDescriptor::default()),
+                surface_texture: None,
+            })),
+        }
+    }
+
+    /// Copies `texture` into a mapped buffer through `encoder`, submits it and waits for the
+    /// pixels. `texture` must have the context size and format.
+    pub(crate) fn read_texture(
+        &self,
+        mut encoder: CommandEncoder,
+        texture: &Texture,
+    ) -> Result<Image, Error> {
+        let width = self.surface_config.width;
+        let height = self.surface_config.height;
+
+        // Rows in the buffer have to be aligned, the padding is stripped after mapping
+        let unpadded_bytes_per_row = width * 4;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT)
+            * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = self.device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: (padded_bytes_per_row * height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            // Nobody is left to tell once the receiver is gone
+            let _ = sender.send(result);
+        });
+        self.device.poll(Maintain::Wait);
+        // A callback dropped without being called never mapped the buffer either
+        receiver.recv().unwrap_or(Err(BufferAsyncError))?;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        for row in slice
+            .get_mapped_range()
+            .chunks_exact(padded_bytes_per_row as usize)
+        {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        buffer.unmap();
+
+        if matches!(
+            self.texture_format,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+        ) {
+            for bgra in pixels.chunks_exact_mut(4) {
+                bgra.swap(0, 2);
+            }
+        }
+
+        // The surface always has at least one pixel and the rows were copied in full
+        Ok(Image::new(width, height, pixels).unwrap())
+    }
+
+    pub(crate) fn request_redraw(&self) {
+        if let Target::Window { window, .. } = &self.target {
+            window.request_redraw();
+        }
     }
 }
 
+/// Features the renderer can't work without.
+const REQUIRED_FEATURES: Features = Features::empty();
+
+/// Features used when the adapter has them, the renderer falls back otherwise.
+const OPTIONAL_FEATURES: Features =
+    Features::MULTI_DRAW_INDIRECT.union(Features::INDIRECT_FIRST_INSTANCE);
+
+async fn request_device(adapter: &Adapter) -> Result<(Device, Queue), Error> {
+    let missing = REQUIRED_FEATURES - adapter.features();
+    if !missing.is_empty() {
+        return Err(Error::MissingFeatures {
+            adapter: adapter.get_info().name,
+            features: missing,
+        });
+    }
+
+    let descriptor = DeviceDescriptor {
+        label: None,
+        features: REQUIRED_FEATURES | (OPTIONAL_FEATURES & adapter.features()),
+        limits: Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits()),
+    };
+    Ok(adapter.request_device(&descriptor, None).await?)
+}
+
+pub(crate) fn create_offscreen_texture(device: &Device, config: &SurfaceConfiguration) -> Texture {
+    let size = Extent3d {
+        width: config.width,
+        height: config.height,
+        depth_or_array_layers: 1,
+    };
+
+    let texture_descriptor = TextureDescriptor {
+        label: None,
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: config.format,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        view_formats: &[],
+    };
+
+    device.create_texture(&texture_descriptor)
+}
+
 fn create_multisample_texture_view(device: &Device, config: &SurfaceConfiguration) -> TextureView {
     let size = Extent3d {
         width: config.width,
@@ -95,3 +369,29 @@ fn create_multisample_texture_view(device: &Device, config: &SurfaceConfiguratio
         .create_texture(&texture_descriptor)
         .create_view(&view_descriptor)
 }
+
+fn create_depth_texture_view(device: &Device, config: &SurfaceConfiguration) -> TextureView {
+    let size = Extent3d {
+        width: config.width,
+        height: config.height,
+        depth_or_array_layers: 1,
+    };
+
+    // Sample count has to match the multisampled color attachment
+    let texture_descriptor = TextureDescriptor {
+        label: None,
+        size,
+        mip_level_count: 1,
+        sample_count: 4,
+        dimension: TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    };
+
+    let view_descriptor = TextureViewDescriptor::default();
+
+    device
+        .create_texture(&texture_descriptor)
+        .create_view(&view_descriptor)
+}
@@ -0,0 +1,118 @@
+use std::{error, fmt};
+
+use wgpu::{BufferAsyncError, CreateSurfaceError, Features, RequestDeviceError, SurfaceError};
+use winit::error::OsError;
+
+use crate::{
+    app::StackError,
+    state::{SceneError, ShapeError},
+};
+
+/// Everything that can go wrong while setting up or running the library.
+#[derive(Debug)]
+pub enum Error {
+    /// The window couldn't be created.
+    WindowCreate(OsError),
+    /// The window doesn't support a surface to render into.
+    CreateSurface(CreateSurfaceError),
+    /// No adapter matched the requested options.
+    NoAdapter,
+    /// The adapter lacks features the renderer needs.
+    MissingFeatures { adapter: String, features: Features },
+    /// The adapter refused to create a device.
+    RequestDevice(RequestDeviceError),
+    /// The surface couldn't provide a texture and can't recover by reconfiguring.
+    Surface(SurfaceError),
+    /// A captured frame couldn't be read back from the device, which was likely lost.
+    Capture(BufferAsyncError),
+    /// A frame ended with a `push` that was never popped.
+    Stack(StackError),
+    /// A shape checked with `App::validate` can't be drawn.
+    Shape(ShapeError),
+    /// A handle passed to `App::validate` doesn't point to a shape.
+    Scene(SceneError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::WindowCreate(error) => write!(f, "failed to create the window: {error}"),
+            Error::CreateSurface(error) => write!(f, "failed to create the surface: {error}"),
+            Error::NoAdapter => write!(f, "no compatible graphics adapter found"),
+            Error::MissingFeatures { adapter, features } => {
+                let names: Vec<_> = features.iter_names().map(|(name, _)| name).collect();
+                write!(f, "adapter {adapter} doesn't support {}", names.join(", "))
+            }
+            Error::RequestDevice(error) => write!(f, "failed to request a device: {error}"),
+            Error::Surface(error) => write!(f, "failed to get the next frame: {error}"),
+            Error::Capture(error) => write!(f, "failed to read the captured frame: {error}"),
+            Error::Stack(error) => error.fmt(f),
+            Error::Shape(error) => error.fmt(f),
+            Error::Scene(error) => error.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::WindowCreate(error) => Some(error),
+            Error::CreateSurface(error) => Some(error),
+            Error::RequestDevice(error) => Some(error),
+            Error::Surface(error) => Some(error),
+            Error::Capture(error) => Some(error),
+            Error::Stack(error) => Some(error),
+            Error::Shape(error) => Some(error),
+            Error::Scene(error) => Some(error),
+            Error::NoAdapter | Error::MissingFeatures { .. } => None,
+        }
+    }
+}
+
+impl From<OsError> for Error {
+    fn from(error: OsError) -> Self {
+        Error::WindowCreate(error)
+    }
+}
+
+impl From<CreateSurfaceError> for Error {
+    fn from(error: CreateSurfaceError) -> Self {
+        Error::CreateSurface(error)
+    }
+}
+
+impl From<RequestDeviceError> for Error {
+    fn from(error: RequestDeviceError) -> Self {
+        Error::RequestDevice(error)
+    }
+}
+
+impl From<SurfaceError> for Error {
+    fn from(error: SurfaceError) -> Self {
+        Error::Surface(error)
+    }
+}
+
+impl From<BufferAsyncError> for Error {
+    fn from(error: BufferAsyncError) -> Self {
+        Error::Capture(error)
+    }
+}
+
+impl From<StackError> for Error {
+    fn from(error: StackError) -> Self {
+        Error::Stack(error)
+    }
+}
+
+impl From<ShapeError> for Error {
+    fn from(error: ShapeError) -> Self {
+        Error::Shape(error)
+    }
+}
+
+impl From<SceneError> for Error {
+    fn from(error: SceneError) -> Self {
+        Error::Scene(error)
+    }
+}
@@ -0,0 +1,529 @@
+//! Vertex and index generation for the built-in shapes.
+//!
+//! Shapes are centered at the origin and about one unit across, flat shapes lie on the XY plane.
+
+use std::{collections::HashMap, f32::consts::PI, iter::repeat};
+
+use glam::{Vec2, Vec3, Vec4};
+
+use wgpu::PrimitiveTopology;
+
+use crate::{app::Mesh, renderer::Vertex, state::Topology};
+
+pub(crate) type Geometry = (Vec<Vertex>, Vec<u32>);
+
+/// Flat shapes face the default camera, which looks down the Z axis.
+pub(crate) const FLAT_NORMAL: Vec3 = Vec3::NEG_Z;
+
+/// Texture coordinates start at the top left corner of the image, V grows downwards.
+fn vertex(position: Vec3, normal: Vec3, uv: Vec2) -> Vertex {
+    Vertex {
+        position: (position, 1.).into(),
+        normal: (normal, 0.).into(),
+        color: Vec4::ONE,
+        uv,
+        ..Default::default()
+    }
+}
+
+/// A point on a flat shape, mapping the `[-0.5, 0.5]` square to the whole texture.
+fn flat_vertex(x: f32, y: f32) -> Vertex {
+    vertex(
+        Vec3::new(x, y, 0.),
+        FLAT_NORMAL,
+        Vec2::new(x + 0.5, 0.5 - y),
+    )
+}
+
+pub(crate) fn triangle(a: Vec3, b: Vec3, c: Vec3) -> Geometry {
+    let normal = (b - a).cross(c - a).normalize_or_zero();
+
+    (
+        vec![
+            vertex(a, normal, Vec2::new(0., 0.)),
+            vertex(b, normal, Vec2::new(1., 0.)),
+            vertex(c, normal, Vec2::new(0., 1.)),
+        ],
+        vec![0, 1, 2],
+    )
+}
+
+pub(crate) fn square() -> Geometry {
+    // The texture covers the square, not the circle around it used by `flat_vertex`
+    let l = f32::sqrt(1. / 8.);
+    let corner = |x: f32, y: f32| {
+        vertex(
+            Vec3::new(x * l, y * l, 0.),
+            FLAT_NORMAL,
+            Vec2::new((x + 1.) / 2., (1. - y) / 2.),
+        )
+    };
+
+    (
+        vec![
+            corner(1., 1.),
+            corner(1., -1.),
+            corner(-1., 1.),
+            corner(-1., -1.),
+        ],
+        vec![0, 1, 2, 1, 2, 3],
+    )
+}
+
+pub(crate) fn polygon(sides: u32) -> Geometry {
+    let vx = (0..sides)
+        .map(|i| (i as f32 / sides as f32) * 2. * PI)
+        .map(|th| [f32::cos(th), f32::sin(th)])
+        .map(|[x, y]| flat_vertex(x / 2., y / 2.))
+        .collect();
+
+    let ix = (0..sides.saturating_sub(2))
+        .flat_map(|i| [0, i + 1, i + 2])
+        .collect();
+
+    (vx, ix)
+}
+
+/// Lines and points are expanded to lists, strips and fans to triangle lists, so every mesh can
+/// be drawn with a list pipeline.
+pub(crate) fn mesh(mesh: &Mesh, topology: &Topology) -> Geometry {
+    let vx = &mesh.vertex;
+
+    // Without explicit indexes the vertices are used in order
+    let sequence: Vec<u32> = if mesh.index.is_empty() {
+        (0..(vx.len() as u32)).collect()
+    } else {
+        mesh.index.clone()
+    };
+    let n = sequence.len();
+
+    let ix: Vec<u32> = match topology {
+        Topology::TriangleList | Topology::LineList | Topology::PointList => sequence,
+        Topology::TriangleStrip => (0..n.saturating_sub(2))
+            .flat_map(|i| [sequence[i], sequence[i + 1], sequence[i + 2]])
+            .collect(),
+        Topology::TriangleFan => (1..n.saturating_sub(1))
+            .flat_map(|i| [sequence[0], sequence[i], sequence[i + 1]])
+            .collect(),
+        Topology::LineStrip => (0..n.saturating_sub(1))
+            .flat_map(|i| [sequence[i], sequence[i + 1]])
+            .collect(),
+    };
+
+    // Normals that weren't given are computed from the triangles around each vertex
+    let computed = match topology.primitive() {
+        PrimitiveTopology::TriangleList => normals(vx, &ix),
+        _ => vec![Vec3::ZERO; vx.len()],
+    };
+    let given = mesh.normal.iter().copied().chain(repeat(Vec3::ZERO));
+    let normals = computed.into_iter().zip(given).map(|(computed, given)| {
+        if given == Vec3::ZERO {
+            computed
+        } else {
+            given.normalize()
+        }
+    });
+
+    let uvs = mesh.uv.iter().copied().chain(repeat(Vec2::ZERO));
+    let colors = mesh.color.iter().copied().chain(repeat(Vec4::ONE));
+
+    let vx = vx
+        .iter()
+        .zip(normals)
+        .zip(uvs)
+        .zip(colors)
+        .map(|(((position, normal), uv), color)| Vertex {
+            color,
+            ..vertex(*position, normal, uv)
+        })
+        .collect();
+
+    (vx, ix)
+}
+
+/// Points around the edge of a flat shape, closed when the last point connects back to the
+/// first.
+pub(crate) type Outline = (Vec<Vec3>, bool);
+
+pub(crate) fn square_outline() -> Outline {
+    let l = f32::sqrt(1. / 8.);
+    let corners = [(1., 1.), (1., -1.), (-1., -1.), (-1., 1.)];
+
+    (
+        corners.map(|(x, y)| Vec3::new(x * l, y * l, 0.)).into(),
+        true,
+    )
+}
+
+pub(crate) fn polygon_outline(sides: u32) -> Outline {
+    let points = (0..sides)
+        .map(|j| circle_point(j, sides))
+        .map(|(x, y)| Vec3::new(x, y, 0.))
+        .collect();
+
+    (points, true)
+}
+
+/// A vertex position as bits, so it can be hashed.
+type PositionKey = [u32; 3];
+
+/// The edges belonging to a single triangle of `geometry`, chained into loops where they meet.
+/// Closed surfaces have no outline.
+pub(crate) fn mesh_outline((vx, ix): &Geometry) -> Vec<Outline> {
+    // Vertices are matched by position, meshes often repeat them to vary other attributes
+    let key = |i: u32| -> PositionKey {
+        vx[i as usize]
+            .position
+            .truncate()
+            .to_array()
+            .map(f32::to_bits)
+    };
+
+    // First occurrence of each edge with the number of triangles sharing it
+    let mut edges: HashMap<(PositionKey, PositionKey), (u32, u32, usize)> = HashMap::new();
+    for face in ix.chunks_exact(3) {
+        for (a, b) in [(face[0], face[1]), (face[1], face[2]), (face[2], face[0])] {
+            let (ka, kb) = (key(a), key(b));
+            let edge = if ka < kb { (ka, kb) } else { (kb, ka) };
+            edges.entry(edge).or_insert((a, b, 0)).2 += 1;
+        }
+    }
+
+    // Boundary edges sorted by vertex index, the map iterates in an arbitrary order and
+    // outlines should come out the same every frame
+    let mut boundary: Vec<(u32, u32)> = edges
+        .into_values()
+        .filter(|&(_, _, count)| count == 1)
+        .map(|(a, b, _)| (a, b))
+        .collect();
+    boundary.sort_unstable();
+
+    let mut neighbours: HashMap<PositionKey, Vec<usize>> = HashMap::new();
+    for (i, &(a, b)) in boundary.iter().enumerate() {
+        neighbours.entry(key(a)).or_default().push(i);
+        neighbours.entry(key(b)).or_default().push(i);
+    }
+
+    let mut used = vec![false; boundary.len()];
+    let mut outlines = vec![];
+
+    for start in 0..boundary.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+
+        let (first, mut last) = boundary[start];
+        let mut points = vec![first, last];
+
+        // Follows unused edges from the end until the loop closes or runs out
+        while let Some(&next) = neighbours[&key(last)].iter().find(|&&i| !used[i]) {
+            used[next] = true;
+            let (a, b) = boundary[next];
+            last = if key(a) == key(last) { b } else { a };
+            points.push(last);
+        }
+
+        let closed = points.len() > 3 && key(first) == key(last);
+        if closed {
+            points.pop();
+        }
+
+        let points = points
+            .into_iter()
+            .map(|i| vx[i as usize].position.truncate())
+            .collect();
+        outlines.push((points, closed));
+    }
+
+    outlines
+}
+
+/// Vertex normals averaged from the faces around each vertex, weighted by their area.
+pub(crate) fn normals(vx: &[Vec3], ix: &[u32]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; vx.len()];
+
+    for face in ix.chunks_exact(3) {
+        let [a, b, c] = [face[0], face[1], face[2]].map(|i| i as usize);
+        let normal = (vx[b] - vx[a]).cross(vx[c] - vx[a]);
+
+        normals[a] += normal;
+        normals[b] += normal;
+        normals[c] += normal;
+    }
+
+    normals.into_iter().map(Vec3::normalize_or_zero).collect()
+}
+
+pub(crate) fn cube() -> Geometry {
+    // Each face gets its own corners so they can carry their own attributes
+    let faces = [
+        (Vec3::X, Vec3::Y),
+        (Vec3::NEG_X, Vec3::Y),
+        (Vec3::Y, Vec3::Z),
+        (Vec3::NEG_Y, Vec3::Z),
+        (Vec3::Z, Vec3::X),
+        (Vec3::NEG_Z, Vec3::X),
+    ];
+
+    let mut vx = Vec::with_capacity(24);
+    let mut ix = Vec::with_capacity(36);
+
+    for (normal, up) in faces {
+        let right = normal.cross(up);
+        let base = vx.len() as u32;
+
+        for (u, v) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)] {
+            let uv = Vec2::new((u + 1.) / 2., (1. - v) / 2.);
+            vx.push(vertex((normal + right * u + up * v) / 2., normal, uv));
+        }
+
+        ix.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+
+    (vx, ix)
+}
+
+/// A sphere made of `resolution` rings of `2 * resolution` quads.
+pub(crate) fn sphere(resolution: u32) -> Geometry {
+    let rings = resolution.max(2);
+    let segments = 2 * rings;
+
+    let mut vx = Vec::with_capacity(((rings + 1) * (segments + 1)) as usize);
+    for i in 0..=rings {
+        let phi = PI * i as f32 / rings as f32;
+        for j in 0..=segments {
+            let theta = 2. * PI * j as f32 / segments as f32;
+            let direction = Vec3::new(phi.sin() * theta.cos(), phi.cos(), phi.sin() * theta.sin());
+            let uv = Vec2::new(j as f32 / segments as f32, i as f32 / rings as f32);
+            vx.push(vertex(direction / 2., direction, uv));
+        }
+    }
+
+    (vx, grid_indexes(rings, segments))
+}
+
+/// A sphere made by splitting every face of an icosahedron in four, `subdivisions` times.
+pub(crate) fn icosphere(subdivisions: u32) -> Geometry {
+    let t = (1. + f32::sqrt(5.)) / 2.;
+
+    let mut points: Vec<Vec3> = [
+        (-1., t, 0.),
+        (1., t, 0.),
+        (-1., -t, 0.),
+        (1., -t, 0.),
+        (0., -1., t),
+        (0., 1., t),
+        (0., -1., -t),
+        (0., 1., -t),
+        (t, 0., -1.),
+        (t, 0., 1.),
+        (-t, 0., -1.),
+        (-t, 0., 1.),
+    ]
+    .into_iter()
+    .map(|p| Vec3::from(p).normalize())
+    .collect();
+
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Edges are shared by two faces, the cache keeps a single midpoint for both
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let p = (points[a as usize] + points[b as usize]).normalize();
+                points.push(p);
+                points.len() as u32 - 1
+            })
+        };
+
+        faces = faces
+            .into_iter()
+            .flat_map(|[a, b, c]| {
+                let ab = midpoint(a, b);
+                let bc = midpoint(b, c);
+                let ca = midpoint(c, a);
+
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // Spherical mapping, the texture wraps around the Y axis
+    let vx = points
+        .into_iter()
+        .map(|p| {
+            let uv = Vec2::new(
+                0.5 + p.z.atan2(p.x) / (2. * PI),
+                p.y.clamp(-1., 1.).acos() / PI,
+            );
+            vertex(p / 2., p, uv)
+        })
+        .collect();
+    let ix = faces.into_iter().flatten().collect();
+
+    (vx, ix)
+}
+
+/// A cylinder along the Y axis with `resolution` sides.
+pub(crate) fn cylinder(resolution: u32) -> Geometry {
+    let sides = resolution.max(3);
+
+    let mut vx = Vec::new();
+    let mut ix = Vec::new();
+
+    for j in 0..=sides {
+        let (x, z) = circle_point(j, sides);
+        let normal = Vec3::new(x, 0., z).normalize();
+        let u = j as f32 / sides as f32;
+        vx.push(vertex(Vec3::new(x, -0.5, z), normal, Vec2::new(u, 1.)));
+        vx.push(vertex(Vec3::new(x, 0.5, z), normal, Vec2::new(u, 0.)));
+    }
+
+    for j in 0..sides {
+        let b0 = 2 * j;
+        let t0 = b0 + 1;
+        let b1 = b0 + 2;
+        let t1 = b0 + 3;
+        ix.extend([b0, t0, b1, b1, t0, t1]);
+    }
+
+    cap(&mut vx, &mut ix, sides, 0.5);
+    cap(&mut vx, &mut ix, sides, -0.5);
+
+    (vx, ix)
+}
+
+/// A cone along the Y axis with `resolution` sides and its apex at the top.
+pub(crate) fn cone(resolution: u32) -> Geometry {
+    let sides = resolution.max(3);
+
+    let mut vx = Vec::new();
+    let mut ix = Vec::new();
+
+    // The apex is repeated for every side so each one can carry its own normal, pointing
+    // halfway between the two base vertices of its side
+    let slope = |angle: f32| Vec3::new(angle.cos(), 0.5, angle.sin()).normalize();
+    for j in 0..=sides {
+        let (x, z) = circle_point(j, sides);
+        let angle = 2. * PI * j as f32 / sides as f32;
+        let apex_angle = 2. * PI * (j as f32 + 0.5) / sides as f32;
+        let u = j as f32 / sides as f32;
+        let apex_u = (j as f32 + 0.5) / sides as f32;
+        vx.push(vertex(
+            Vec3::new(x, -0.5, z),
+            slope(angle),
+            Vec2::new(u, 1.),
+        ));
+        vx.push(vertex(
+            Vec3::new(0., 0.5, 0.),
+            slope(apex_angle),
+            Vec2::new(apex_u, 0.),
+        ));
+    }
+
+    for j in 0..sides {
+        ix.extend([2 * j, 2 * j + 1, 2 * j + 2]);
+    }
+
+    cap(&mut vx, &mut ix, sides, -0.5);
+
+    (vx, ix)
+}
+
+/// A torus around the Z axis, its ring has a radius of one half and its tube a radius of
+/// `thickness`.
+pub(crate) fn torus(resolution: u32, thickness: f32) -> Geometry {
+    let rings = resolution.max(3);
+    let tube = (rings / 2).max(3);
+
+    let mut vx = Vec::with_capacity(((rings + 1) * (tube + 1)) as usize);
+    for i in 0..=rings {
+        let u = 2. * PI * i as f32 / rings as f32;
+        for j in 0..=tube {
+            let v = 2. * PI * j as f32 / tube as f32;
+            let r = 0.5 + thickness * v.cos();
+            let position = Vec3::new(r * u.cos(), r * u.sin(), thickness * v.sin());
+            let normal = Vec3::new(v.cos() * u.cos(), v.cos() * u.sin(), v.sin());
+            let uv = Vec2::new(i as f32 / rings as f32, j as f32 / tube as f32);
+            vx.push(vertex(position, normal, uv));
+        }
+    }
+
+    (vx, grid_indexes(rings, tube))
+}
+
+/// A square on the XY plane split into `resolution` by `resolution` quads.
+pub(crate) fn plane(resolution: u32) -> Geometry {
+    let n = resolution.max(1);
+
+    let mut vx = Vec::with_capacity(((n + 1) * (n + 1)) as usize);
+    for i in 0..=n {
+        let y = i as f32 / n as f32 - 0.5;
+        for j in 0..=n {
+            let x = j as f32 / n as f32 - 0.5;
+            vx.push(flat_vertex(x, y));
+        }
+    }
+
+    (vx, grid_indexes(n, n))
+}
+
+fn circle_point(j: u32, sides: u32) -> (f32, f32) {
+    let theta = 2. * PI * j as f32 / sides as f32;
+    (theta.cos() / 2., theta.sin() / 2.)
+}
+
+/// Closes a cylinder or cone at height `y` with a fan around its center.
+fn cap(vx: &mut Vec<Vertex>, ix: &mut Vec<u32>, sides: u32, y: f32) {
+    let normal = Vec3::new(0., y.signum(), 0.);
+    let center = vx.len() as u32;
+    vx.push(vertex(Vec3::new(0., y, 0.), normal, Vec2::splat(0.5)));
+
+    for j in 0..sides {
+        let (x, z) = circle_point(j, sides);
+        let uv = Vec2::new(x + 0.5, z + 0.5);
+        vx.push(vertex(Vec3::new(x, y, z), normal, uv));
+    }
+
+    for j in 0..sides {
+        ix.extend([center, center + 1 + j, center + 1 + (j + 1) % sides]);
+    }
+}
+
+/// Two triangles for every cell of a `rows` by `columns` grid of vertices laid out row by row,
+/// with `columns + 1` vertices on each row.
+fn grid_indexes(rows: u32, columns: u32) -> Vec<u32> {
+    let stride = columns + 1;
+
+    (0..rows)
+        .flat_map(|i| (0..columns).map(move |j| (i, j)))
+        .flat_map(|(i, j)| {
+            let a = i * stride + j;
+            let b = a + stride;
+            [a, b, a + 1, a + 1, b, b + 1]
+        })
+        .collect()
+}
@@ -0,0 +1,144 @@
+use std::{
+    error, fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// An 8-bit RGBA image, stored row by row without padding.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Why pixels can't make an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    /// A width or height of zero, textures need at least one pixel.
+    Empty { width: u32, height: u32 },
+    /// More pixels than fit in memory.
+    TooLarge { width: u32, height: u32 },
+    /// A buffer that doesn't hold exactly four bytes for every pixel.
+    Length { expected: usize, actual: usize },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Empty { width, height } => {
+                write!(f, "image of {width}x{height} has no pixels")
+            }
+            ImageError::TooLarge { width, height } => {
+                write!(f, "image of {width}x{height} is too large")
+            }
+            ImageError::Length { expected, actual } => {
+                write!(f, "image needs {expected} bytes of pixels, got {actual}")
+            }
+        }
+    }
+}
+
+impl error::Error for ImageError {}
+
+impl Image {
+    /// Checks that `pixels` holds four bytes for each of the `width` by `height` pixels.
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Result<Self, ImageError> {
+        if width == 0 || height == 0 {
+            return Err(ImageError::Empty { width, height });
+        }
+
+        let expected = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|count| count.checked_mul(4))
+            .ok_or(ImageError::TooLarge { width, height })?;
+        if pixels.len() != expected {
+            return Err(ImageError::Length {
+                expected,
+                actual: pixels.len(),
+            });
+        }
+
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Returns the RGBA value of the pixel at `(x, y)`, counted from the top left corner.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        [
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+            self.pixels[i + 3],
+        ]
+    }
+
+    pub fn read_png(path: impl AsRef<Path>) -> io::Result<Image> {
+        Image::decode_png(&fs::read(path)?)
+    }
+
+    /// Decodes a PNG of any color type and bit depth into 8-bit RGBA.
+    pub fn decode_png(bytes: &[u8]) -> io::Result<Image> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let data = &buffer[..info.buffer_size()];
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => data.to_vec(),
+            png::ColorType::Rgb => data
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => data
+                .chunks_exact(2)
+                .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            png::ColorType::Grayscale => data.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+            // Palettes are expanded to RGB(A) by the transformations above
+            png::ColorType::Indexed => unreachable!(),
+        };
+
+        Image::new(info.width, info.height, pixels)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    pub fn write_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.encode_png(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn encode_png(&self, writer: impl Write) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    /// Writes a binary PPM (P6), the alpha channel is dropped.
+    pub fn write_ppm(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.encode_ppm(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn encode_ppm(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+
+        for rgba in self.pixels.chunks_exact(4) {
+            writer.write_all(&rgba[..3])?;
+        }
+
+        writer.flush()
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+
+use glam::Vec2;
+use winit::event::{ElementState, KeyboardInput, MouseScrollDelta, WindowEvent};
+pub use winit::event::{MouseButton, VirtualKeyCode as Key};
+
+/// Pixels per scroll line, used to express touchpad scrolling in lines.
+const LINE_HEIGHT: f32 = 20.;
+
+/// Keyboard and mouse state, `pressed`/`released`, `scroll` and `text` only cover the
+/// current frame.
+#[derive(Default)]
+pub struct Input {
+    /// Cursor position in pixels, from the top left corner of the window.
+    pub mouse: Vec2,
+    /// Scroll since the last frame, in lines.
+    pub scroll: Vec2,
+    /// Characters typed since the last frame.
+    pub text: String,
+    keys_down: HashSet<Key>,
+    keys_pressed: HashSet<Key>,
+    keys_released: HashSet<Key>,
+    buttons_down: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+}
+
+impl Input {
+    pub fn key_down(&self, key: Key) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn key_pressed(&self, key: Key) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn key_released(&self, key: Key) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn button_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn button_released(&self, button: MouseButton) -> bool {
+        self.buttons_released.contains(&button)
+    }
+
+    pub(crate) fn event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => match state {
+                // Key repeat sends more presses while the key is held
+                ElementState::Pressed => {
+                    if self.keys_down.insert(*key) {
+                        self.keys_pressed.insert(*key);
+                    }
+                }
+                ElementState::Released => {
+                    self.keys_down.remove(key);
+                    self.keys_released.insert(*key);
+                }
+            },
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.buttons_down.insert(*button);
+                    self.buttons_pressed.insert(*button);
+                }
+                ElementState::Released => {
+                    self.buttons_down.remove(button);
+                    self.buttons_released.insert(*button);
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                self.mouse = Vec2::new(position.x as f32, position.y as f32);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(x, y) => Vec2::new(*x, *y),
+                    MouseScrollDelta::PixelDelta(position) => {
+                        Vec2::new(position.x as f32, position.y as f32) / LINE_HEIGHT
+                    }
+                };
+            }
+            WindowEvent::ReceivedCharacter(c) if !c.is_control() => {
+                self.text.push(*c);
+            }
+            WindowEvent::Focused(false) => {
+                // Releases are lost while unfocused
+                self.keys_down.clear();
+                self.buttons_down.clear();
+            }
+            _ => (),
+        }
+    }
+
+    /// Forgets the per frame state, called after each frame is drawn.
+    pub(crate) fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.scroll = Vec2::ZERO;
+        self.text.clear();
+    }
+}
@@ -1,39 +1,117 @@
-use crate::context::Context;
+use std::time::Duration;
+
+use crate::{context::Context, error::Error};
 
 use app::App;
 use renderer::Renderer;
 use winit::{
     event::{Event, WindowEvent},
     event_loop::EventLoop,
+    platform::run_return::EventLoopExtRunReturn,
     window::Window,
 };
 
 pub mod app;
+mod buffer;
 pub mod color;
 pub mod context;
+pub mod error;
+mod geometry;
+pub mod image;
+pub mod input;
+mod line;
+mod path;
 pub mod renderer;
 pub mod state;
+pub mod time;
+
+/// Opens a window and runs the event loop until the window is closed.
+///
+/// Returns early with an error if the window or the graphics device can't be set up, when a
+/// frame ends with a `push` that was never popped, or when the surface fails in a way
+/// reconfiguring it can't fix. The window closes when it returns, so the caller can show
+/// the message instead of aborting. Errors the device reports on its own, like a lost device,
+/// go to [`App::on_device_error`].
+///
+/// `init` is called once to create the user state, `update` is called on every frame with the
+/// same `App` and state. The scene built on `App` is cleared before each call to `update`, while
+/// the camera, background and other settings are kept. See [`App::retained`] to keep the scene
+/// instead.
+pub async fn run<S: 'static>(
+    init: impl FnOnce(&mut App) -> S,
+    update: impl FnMut(&mut App, &mut S) + 'static,
+) -> Result<(), Error> {
+    run_loop(init, None::<(Duration, fn(&mut App, &mut S))>, update).await
+}
 
-pub async fn run(redraw: fn(&mut App)) {
-    let event_loop = EventLoop::new();
+/// Like [`run`], with an additional `fixed_update` called every `timestep` of wall-clock time,
+/// zero or more times before each `update`.
+///
+/// `update` can use `app.time.alpha` to interpolate between the last two fixed updates.
+pub async fn run_fixed<S: 'static>(
+    init: impl FnOnce(&mut App) -> S,
+    timestep: Duration,
+    fixed_update: impl FnMut(&mut App, &mut S) + 'static,
+    update: impl FnMut(&mut App, &mut S) + 'static,
+) -> Result<(), Error> {
+    run_loop(init, Some((timestep, fixed_update)), update).await
+}
+
+async fn run_loop<S: 'static, F: FnMut(&mut App, &mut S) + 'static>(
+    init: impl FnOnce(&mut App) -> S,
+    mut fixed: Option<(Duration, F)>,
+    mut update: impl FnMut(&mut App, &mut S) + 'static,
+) -> Result<(), Error> {
+    let mut event_loop = EventLoop::new();
 
-    let window = Window::new(&event_loop);
-    let window = window.expect("WindowCreateFailed");
+    let window = Window::new(&event_loop)?;
 
-    let context = Context::new(window).await;
+    let context = Context::new(window).await?;
     let mut renderer = Renderer::new(context);
 
-    event_loop.run(move |event, _target, control_flow| {
+    let mut app = App::new(
+        renderer.context.surface_config.width,
+        renderer.context.surface_config.height,
+    );
+    let mut state = init(&mut app);
+    if let Some(handler) = app.device_error.take() {
+        renderer.context.on_device_error(handler);
+    }
+
+    let mut result = Ok(());
+
+    event_loop.run_return(|event, _target, control_flow| {
         control_flow.set_poll();
-        let mut app = App::new(
-            renderer.context.surface_config.width,
-            renderer.context.surface_config.height,
-        );
 
         match event {
             Event::RedrawRequested(_) => {
-                redraw(&mut app);
-                renderer.render(app.state.serialize());
+                app.time.tick();
+
+                if let Some((timestep, fixed_update)) = &mut fixed {
+                    app.time.accumulate();
+                    while app.time.fixed_step(*timestep) {
+                        fixed_update(&mut app, &mut state);
+                    }
+                }
+
+                if !app.retained {
+                    app.clear();
+                }
+                update(&mut app, &mut state);
+                if let Some(handler) = app.device_error.take() {
+                    renderer.context.on_device_error(handler);
+                }
+                if let Err(error) = app.end_frame() {
+                    result = Err(error.into());
+                    control_flow.set_exit();
+                    return;
+                }
+                if let Err(error) = renderer.draw(&mut app) {
+                    result = Err(error);
+                    control_flow.set_exit();
+                    return;
+                }
+                app.input.end_frame();
                 app.frame += 1;
             }
             Event::WindowEvent {
@@ -41,8 +119,7 @@ pub async fn run(redraw: fn(&mut App)) {
                 event: WindowEvent::Resized(size),
             } => {
                 renderer.context.resize(size);
-                app.size.width = size.width;
-                app.size.height = size.height;
+                app.resize(size.width, size.height);
             }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
@@ -50,7 +127,12 @@ pub async fn run(redraw: fn(&mut App)) {
             } => {
                 control_flow.set_exit();
             }
+            Event::WindowEvent { event, .. } => {
+                app.input.event(&event);
+            }
             _ => (),
         };
     });
+
+    result
 }
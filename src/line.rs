@@ -0,0 +1,312 @@
+//! Tessellation of lines into triangles.
+//!
+//! Lines are tessellated in screen space, where widths, joins and caps are two dimensional,
+//! and every vertex is projected back into the world at the depth of the point it belongs to.
+//! The result depends on the camera and viewport, so it is redone whenever they change.
+
+use std::f32::consts::PI;
+
+use glam::{Mat4, Vec2, Vec3, Vec3Swizzles, Vec4};
+
+use crate::{
+    geometry::{Geometry, Outline},
+    renderer::Vertex,
+    state::{Camera, LineCap, LineJoin, LineStyle, LineWidth},
+};
+
+/// Miters longer than this many half widths are beveled instead.
+const MITER_LIMIT: f32 = 4.;
+
+/// Largest angle covered by one triangle of a round join or cap.
+const ROUND_STEP: f32 = PI / 16.;
+
+/// The camera and viewport lines are tessellated for.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct View {
+    matrix: Mat4,
+    /// Perpendicular to the view direction, measures world widths in pixels
+    right: Vec3,
+    viewport: Vec2,
+}
+
+impl View {
+    pub(crate) fn new(camera: &Camera, viewport: Vec2) -> View {
+        let forward = camera.view.center - camera.view.eye;
+
+        View {
+            matrix: camera.matrix(),
+            right: forward.cross(camera.view.up).normalize_or_zero(),
+            viewport,
+        }
+    }
+
+    /// Pixel position from the top left corner, with the depth in the z coordinate. `None`
+    /// behind the camera.
+    fn project(&self, point: Vec3) -> Option<Vec3> {
+        let clip = self.matrix * point.extend(1.);
+        if clip.w <= 0. {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+
+        Some(Vec3::new(
+            (ndc.x + 1.) / 2. * self.viewport.x,
+            (1. - ndc.y) / 2. * self.viewport.y,
+            ndc.z,
+        ))
+    }
+
+    /// The points on the `z = 0` plane of the world under `pixels`.
+    pub(crate) fn pixels_to_world(&self, pixels: &[Vec2]) -> Vec<Vec3> {
+        let inverse = self.matrix.inverse();
+
+        pixels
+            .iter()
+            .map(|pixel| {
+                let near = self.unproject(&inverse, pixel.extend(0.));
+                let far = self.unproject(&inverse, pixel.extend(1.));
+                near.lerp(far, near.z / (near.z - far.z))
+            })
+            .collect()
+    }
+
+    /// Half of `width` in pixels at the point `position` was projected from.
+    fn half_width(&self, point: Vec3, position: Vec3, width: LineWidth) -> f32 {
+        match width {
+            LineWidth::Screen(width) => width / 2.,
+            LineWidth::World(width) => self
+                .project(point + self.right * width)
+                .map_or(0., |edge| edge.xy().distance(position.xy()) / 2.),
+        }
+    }
+
+    fn unproject(&self, inverse: &Mat4, screen: Vec3) -> Vec3 {
+        let ndc = Vec3::new(
+            screen.x / self.viewport.x * 2. - 1.,
+            1. - screen.y / self.viewport.y * 2.,
+            screen.z,
+        );
+
+        inverse.project_point3(ndc)
+    }
+}
+
+/// Tessellates the line through the world space `points`. Lines crossing behind the camera
+/// are left out.
+pub(crate) fn line(points: &[Vec3], closed: bool, style: &LineStyle, view: &View) -> Geometry {
+    // Screen positions with the half width in pixels at each point
+    let mut screen: Vec<(Vec3, f32)> = Vec::with_capacity(points.len());
+    for &point in points {
+        let Some(position) = view.project(point) else {
+            return (vec![], vec![]);
+        };
+
+        let half_width = view.half_width(point, position, style.width);
+
+        // Points on the same pixel leave the line without a direction
+        let repeated = screen
+            .last()
+            .is_some_and(|(last, _)| last.xy().distance(position.xy()) < 1e-3);
+        if !repeated {
+            screen.push((position, half_width));
+        }
+    }
+
+    if closed
+        && screen.len() > 2
+        && screen[0].0.xy().distance(screen[screen.len() - 1].0.xy()) < 1e-3
+    {
+        screen.pop();
+    }
+    if screen.len() < 2 {
+        return (vec![], vec![]);
+    }
+
+    let mut tessellator = Tessellator::default();
+    let n = screen.len();
+
+    let segments = if closed { n } else { n - 1 };
+    for i in 0..segments {
+        let (a, a_width) = screen[i];
+        let (b, b_width) = screen[(i + 1) % n];
+        let normal = direction(a, b).perp();
+
+        tessellator.quad([
+            offset(a, normal * a_width),
+            offset(a, -normal * a_width),
+            offset(b, -normal * b_width),
+            offset(b, normal * b_width),
+        ]);
+    }
+
+    let joins = if closed { 0..n } else { 1..n - 1 };
+    for i in joins {
+        let (previous, _) = screen[(i + n - 1) % n];
+        let (point, width) = screen[i];
+        let (next, _) = screen[(i + 1) % n];
+        tessellator.join(
+            point,
+            width,
+            direction(previous, point),
+            direction(point, next),
+            style.join,
+        );
+    }
+
+    if !closed {
+        let (first, first_width) = screen[0];
+        let (last, last_width) = screen[n - 1];
+        tessellator.cap(
+            first,
+            first_width,
+            -direction(first, screen[1].0),
+            style.cap,
+        );
+        tessellator.cap(
+            last,
+            last_width,
+            direction(screen[n - 2].0, last),
+            style.cap,
+        );
+    }
+
+    tessellator.finish(view)
+}
+
+/// A round dot `width` across at `point`, left out behind the camera.
+pub(crate) fn dot(point: Vec3, width: LineWidth, view: &View) -> Geometry {
+    let Some(position) = view.project(point) else {
+        return (vec![], vec![]);
+    };
+
+    let mut tessellator = Tessellator::default();
+    let radius = view.half_width(point, position, width);
+    tessellator.fan(position, Vec2::X * radius, 2. * PI);
+
+    tessellator.finish(view)
+}
+
+/// Tessellates every outline into the same geometry.
+pub(crate) fn outlines(outlines: &[Outline], style: &LineStyle, view: &View) -> Geometry {
+    let (mut vx, mut ix) = (vec![], vec![]);
+
+    for (points, closed) in outlines {
+        let (line_vx, line_ix) = line(points, *closed, style, view);
+        let start = vx.len() as u32;
+        vx.extend(line_vx);
+        ix.extend(line_ix.into_iter().map(|i| start + i));
+    }
+
+    (vx, ix)
+}
+
+fn direction(from: Vec3, to: Vec3) -> Vec2 {
+    (to.xy() - from.xy()).normalize()
+}
+
+fn offset(point: Vec3, by: Vec2) -> Vec3 {
+    point + by.extend(0.)
+}
+
+/// Screen space triangles, depth carried in the z coordinate.
+#[derive(Default)]
+struct Tessellator {
+    positions: Vec<Vec3>,
+    indexes: Vec<u32>,
+}
+
+impl Tessellator {
+    fn triangle(&mut self, corners: [Vec3; 3]) {
+        let start = self.positions.len() as u32;
+        self.positions.extend(corners);
+        self.indexes.extend([start, start + 1, start + 2]);
+    }
+
+    /// Projects the triangles back into the world.
+    fn finish(self, view: &View) -> Geometry {
+        let inverse = view.matrix.inverse();
+        let vx = self
+            .positions
+            .into_iter()
+            .map(|position| {
+                let world = view.unproject(&inverse, position);
+                // Lines have no normal and are never lit
+                Vertex {
+                    position: (world, 1.).into(),
+                    color: Vec4::ONE,
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        (vx, self.indexes)
+    }
+
+    fn quad(&mut self, [a, b, c, d]: [Vec3; 4]) {
+        self.triangle([a, b, c]);
+        self.triangle([a, c, d]);
+    }
+
+    /// Triangles around `center` from `from` rotated by `sweep` radians.
+    fn fan(&mut self, center: Vec3, from: Vec2, sweep: f32) {
+        let steps = (sweep.abs() / ROUND_STEP).ceil().max(1.) as u32;
+        let angle = |step: u32| sweep * step as f32 / steps as f32;
+
+        for step in 0..steps {
+            let a = Vec2::from_angle(angle(step)).rotate(from);
+            let b = Vec2::from_angle(angle(step + 1)).rotate(from);
+            self.triangle([center, offset(center, a), offset(center, b)]);
+        }
+    }
+
+    /// Fills the gap on the outer side of the turn from `incoming` to `outgoing`.
+    fn join(&mut self, point: Vec3, width: f32, incoming: Vec2, outgoing: Vec2, join: LineJoin) {
+        let turn = incoming.perp_dot(outgoing);
+        if width <= 0. || turn.abs() < 1e-6 && incoming.dot(outgoing) > 0. {
+            return;
+        }
+
+        // The segment quads overlap on the inner side and leave a gap on the outer one
+        let side = if turn > 0. { -1. } else { 1. };
+        let from = incoming.perp() * side * width;
+        let to = outgoing.perp() * side * width;
+
+        match join {
+            LineJoin::Round => self.fan(point, from, from.angle_between(to)),
+            LineJoin::Miter => {
+                let miter = (from + to).normalize_or_zero();
+                let cos = miter.dot(from / width);
+                let length = width / cos;
+
+                if miter == Vec2::ZERO || length > MITER_LIMIT * width {
+                    self.triangle([point, offset(point, from), offset(point, to)]);
+                } else {
+                    let tip = offset(point, miter * length);
+                    self.triangle([point, offset(point, from), tip]);
+                    self.triangle([point, tip, offset(point, to)]);
+                }
+            }
+            LineJoin::Bevel => self.triangle([point, offset(point, from), offset(point, to)]),
+        }
+    }
+
+    /// Closes an open end at `point`, where the line leaves along `outward`.
+    fn cap(&mut self, point: Vec3, width: f32, outward: Vec2, cap: LineCap) {
+        let normal = outward.perp() * width;
+
+        match cap {
+            LineCap::Butt => {}
+            LineCap::Square => {
+                let extension = outward * width;
+                self.quad([
+                    offset(point, normal),
+                    offset(point, -normal),
+                    offset(point, -normal + extension),
+                    offset(point, normal + extension),
+                ]);
+            }
+            // perp turns counterclockwise, so going clockwise from it passes through outward
+            LineCap::Round => self.fan(point, normal, -PI),
+        }
+    }
+}
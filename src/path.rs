@@ -0,0 +1,169 @@
+//! Flat shapes given in pixels from the top left corner of the window.
+//!
+//! Points are placed on the `z = 0` plane of the world where they appear on screen, so their
+//! geometry depends on the camera and viewport like lines.
+
+use std::f32::consts::PI;
+
+use glam::{Vec2, Vec3, Vec4};
+
+use crate::{geometry::Geometry, renderer::Vertex};
+
+/// Largest distance in pixels between a curve and the straight segments drawn for it.
+const TOLERANCE: f32 = 0.25;
+
+pub(crate) fn rect(x: f32, y: f32, w: f32, h: f32) -> Vec<Vec2> {
+    vec![
+        Vec2::new(x, y),
+        Vec2::new(x + w, y),
+        Vec2::new(x + w, y + h),
+        Vec2::new(x, y + h),
+    ]
+}
+
+/// A rectangle with its corners rounded by `radius`, at most half its shorter side.
+pub(crate) fn rounded_rect(x: f32, y: f32, w: f32, h: f32, radius: f32) -> Vec<Vec2> {
+    let r = radius.min(w.abs() / 2.).min(h.abs() / 2.);
+    if r <= 0. {
+        return rect(x, y, w, h);
+    }
+
+    let (left, right) = (x.min(x + w) + r, x.max(x + w) - r);
+    let (top, bottom) = (y.min(y + h) + r, y.max(y + h) - r);
+    let corners = [
+        (Vec2::new(right, top), -PI / 2.),
+        (Vec2::new(right, bottom), 0.),
+        (Vec2::new(left, bottom), PI / 2.),
+        (Vec2::new(left, top), PI),
+    ];
+
+    corners
+        .into_iter()
+        .flat_map(|(center, start)| arc(center, Vec2::splat(r), start, start + PI / 2.))
+        .collect()
+}
+
+/// Points along an ellipse from `start` to `stop`, angles grow clockwise from the right.
+pub(crate) fn arc(center: Vec2, radii: Vec2, start: f32, stop: f32) -> Vec<Vec2> {
+    let sweep = (stop - start).clamp(-2. * PI, 2. * PI);
+    let full = sweep.abs() >= 2. * PI;
+
+    let steps = segments(radii.max_element(), sweep.abs());
+    // A full ellipse would repeat its first point at the end
+    let points = if full { steps } else { steps + 1 };
+
+    (0..points)
+        .map(|i| start + sweep * i as f32 / steps as f32)
+        .map(|angle| center + radii * Vec2::new(angle.cos(), angle.sin()))
+        .collect()
+}
+
+/// Points alternating between the outer and inner radius, starting at the top.
+pub(crate) fn star(center: Vec2, inner: f32, outer: f32, points: u32) -> Vec<Vec2> {
+    (0..points * 2)
+        .map(|i| {
+            let angle = -PI / 2. + PI * i as f32 / points as f32;
+            let radius = if i % 2 == 0 { outer } else { inner };
+            center + radius * Vec2::new(angle.cos(), angle.sin())
+        })
+        .collect()
+}
+
+/// Straight segments needed to follow `sweep` radians of a circle of `radius` within the
+/// tolerance.
+fn segments(radius: f32, sweep: f32) -> u32 {
+    let step = if radius > TOLERANCE {
+        2. * (1. - TOLERANCE / radius).acos()
+    } else {
+        PI / 2.
+    };
+
+    ((sweep / step).ceil() as u32).clamp(1, 1024)
+}
+
+/// Fills the polygon through `pixels`, placed at `world`, with `normal`. The texture covers the
+/// bounding box of the pixels.
+pub(crate) fn fill(pixels: &[Vec2], world: &[Vec3], normal: Vec3) -> Geometry {
+    let min = pixels.iter().copied().fold(Vec2::MAX, Vec2::min);
+    let max = pixels.iter().copied().fold(Vec2::MIN, Vec2::max);
+    let size = (max - min).max(Vec2::splat(f32::EPSILON));
+
+    let vx = pixels
+        .iter()
+        .zip(world)
+        .map(|(pixel, position)| Vertex {
+            position: (*position, 1.).into(),
+            normal: (normal, 0.).into(),
+            color: Vec4::ONE,
+            uv: (*pixel - min) / size,
+            ..Default::default()
+        })
+        .collect();
+
+    (vx, triangulate(pixels))
+}
+
+/// Triangulates a simple polygon, convex or not, by clipping ears.
+pub(crate) fn triangulate(points: &[Vec2]) -> Vec<u32> {
+    let mut remaining: Vec<u32> = (0..points.len() as u32).collect();
+    let mut ix = Vec::with_capacity(points.len().saturating_sub(2) * 3);
+    if remaining.len() < 3 {
+        return ix;
+    }
+
+    let at = |i: u32| points[i as usize];
+    let area: f32 = (0..points.len())
+        .map(|i| points[i].perp_dot(points[(i + 1) % points.len()]))
+        .sum();
+    // Ears turn the same way as the whole polygon
+    let winding = area.signum();
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let [a, b, c] = [
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            ];
+            let (pa, pb, pc) = (at(a), at(b), at(c));
+            if (pb - pa).perp_dot(pc - pb) * winding <= 0. {
+                return false;
+            }
+
+            !remaining
+                .iter()
+                .filter(|&&j| j != a && j != b && j != c)
+                .any(|&j| inside(at(j), pa, pb, pc))
+        });
+
+        // Degenerate polygons have no ears left, the rest is fanned out
+        let Some(i) = ear else {
+            break;
+        };
+
+        ix.extend([
+            remaining[(i + n - 1) % n],
+            remaining[i],
+            remaining[(i + 1) % n],
+        ]);
+        remaining.remove(i);
+    }
+
+    for i in 1..remaining.len() - 1 {
+        ix.extend([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+
+    ix
+}
+
+/// Whether `p` is inside or on the edge of the triangle.
+fn inside(p: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    let d1 = (b - a).perp_dot(p - a);
+    let d2 = (c - b).perp_dot(p - b);
+    let d3 = (a - c).perp_dot(p - c);
+
+    let negative = d1 < 0. || d2 < 0. || d3 < 0.;
+    let positive = d1 > 0. || d2 > 0. || d3 > 0.;
+    !(negative && positive)
+}
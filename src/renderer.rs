@@ -1,30 +1,88 @@
+use std::{collections::HashMap, mem::size_of, ops::Range, sync::Arc};
+
 use bytemuck::{Pod, Zeroable};
-use glam::{Mat4, Vec4};
+use glam::{Mat4, Vec2, Vec4};
 use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt, DrawIndexedIndirect},
-    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingType, BufferBindingType, Color, CommandEncoderDescriptor,
-    FragmentState, IndexFormat, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor,
-    PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
-    RenderPipelineDescriptor, ShaderStages, TextureViewDescriptor, VertexAttribute,
-    VertexBufferLayout, VertexState,
+    util::DrawIndexedIndirect, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BlendState,
+    Buffer, BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites,
+    CommandEncoder, CommandEncoderDescriptor, CompareFunction, DepthBiasState, DepthStencilState,
+    Extent3d, Features, FilterMode, FragmentState, ImageDataLayout, IndexFormat, LoadOp,
+    MultisampleState, Operations, PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology,
+    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
+    RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderStages, StencilState, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+    VertexAttribute, VertexBufferLayout, VertexState,
 };
 
-use crate::context::Context;
+use crate::{
+    app::App,
+    buffer::GrowableBuffer,
+    color::Color,
+    context::{create_offscreen_texture, Context, Target, DEPTH_FORMAT},
+    error::Error,
+    image::Image,
+    state::{Batch, TextureId},
+};
 
 pub struct Renderer {
     pub context: Context,
-    render_pipeline: RenderPipeline,
+    texture_layout: BindGroupLayout,
+    sampler: Sampler,
+    /// One pipeline per primitive topology, with and without depth testing
+    pipelines: HashMap<(PrimitiveTopology, bool), RenderPipeline>,
+    /// Bound for untextured shapes, shapes are multiplied by their texture
+    white_texture: BindGroup,
+    /// Uploaded textures, indexed by `TextureId`
+    textures: Vec<BindGroup>,
+    vertex_buffer: GrowableBuffer,
+    index_buffer: GrowableBuffer,
+    instance_buffer: GrowableBuffer,
+    /// Only with `MULTI_DRAW_INDIRECT` and `INDIRECT_FIRST_INSTANCE`, other adapters draw one
+    /// entry at a time
+    draws_buffer: Option<GrowableBuffer>,
+    camera_buffer: Buffer,
+    lights_buffer: Buffer,
+    /// Camera and lights, bound for the whole pass
+    bind_group: BindGroup,
+    /// Generation of the scene in the buffers
+    uploaded: Option<u64>,
 }
 
 #[derive(Debug)]
 pub struct RenderState {
-    pub(crate) vertexes: Vec<Vec4>,
+    pub(crate) scene: Arc<Scene>,
+    pub(crate) textures: Vec<Arc<Image>>,
+    pub(crate) clear: wgpu::Color,
+    pub(crate) camera: CameraUniform,
+    pub(crate) lights: LightsUniform,
+    pub(crate) depth_test: bool,
+    /// Ranges of `scene.instances` changed since the previous frame, when the rest of the scene
+    /// is the same
+    pub(crate) instance_writes: Vec<Range<u32>>,
+}
+
+/// The serialized node tree, shared between frames while the scene doesn't change.
+#[derive(Debug, Default, Clone)]
+pub struct Scene {
+    pub(crate) vertexes: Vec<Vertex>,
     pub(crate) indexes: Vec<u32>,
     pub(crate) instances: Vec<Instance>,
     pub(crate) draws: Vec<DrawIndexedIndirect>,
-    pub(crate) clear: Color,
-    pub(crate) camera: Mat4,
+    pub(crate) batches: Vec<Batch>,
+    /// Unique to each serialization, the renderer skips uploading a scene it already has
+    pub(crate) generation: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct Vertex {
+    pub(crate) position: Vec4,
+    pub(crate) normal: Vec4,
+    pub(crate) color: Vec4,
+    pub(crate) uv: Vec2,
+    pub(crate) _padding: Vec2,
 }
 
 #[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
@@ -34,85 +92,356 @@ pub struct Instance {
     pub(crate) color: Vec4,
 }
 
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct CameraUniform {
+    pub(crate) matrix: Mat4,
+    pub(crate) eye: Vec4,
+}
+
+/// Maximum number of directional and of point lights, extra lights are ignored.
+pub const MAX_LIGHTS: usize = 8;
+
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct LightUniform {
+    /// Direction for directional lights, position for point lights
+    pub(crate) vector: Vec4,
+    pub(crate) color: Vec4,
+}
+
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct LightsUniform {
+    pub(crate) ambient: Vec4,
+    /// Lighting enabled, directional and point light counts
+    pub(crate) counts: [u32; 4],
+    pub(crate) directional: [LightUniform; MAX_LIGHTS],
+    pub(crate) point: [LightUniform; MAX_LIGHTS],
+}
+
 impl Renderer {
     pub fn new(context: Context) -> Renderer {
-        let render_pipeline = Renderer::create_pipeline(&context);
+        let bind_group_layout = Renderer::create_bind_group_layout(&context);
+        let texture_layout = Renderer::create_texture_layout(&context);
+        let layouts = [&bind_group_layout, &texture_layout];
+        let topologies = [
+            PrimitiveTopology::TriangleList,
+            PrimitiveTopology::LineList,
+            PrimitiveTopology::PointList,
+        ];
+        let pipelines = topologies
+            .into_iter()
+            .flat_map(|topology| [(topology, false), (topology, true)])
+            .map(|(topology, depth_test)| {
+                let pipeline = Renderer::create_pipeline(&context, &layouts, topology, depth_test);
+                ((topology, depth_test), pipeline)
+            })
+            .collect();
+
+        let sampler = context.device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let white = Image::new(1, 1, vec![255; 4]).unwrap();
+        let white_texture =
+            Renderer::create_texture_bind_group(&context, &texture_layout, &sampler, &white);
+
+        let device = &context.device;
+        let vertex_buffer = GrowableBuffer::new(device, BufferUsages::VERTEX);
+        let index_buffer = GrowableBuffer::new(device, BufferUsages::INDEX);
+        let instance_buffer = GrowableBuffer::new(device, BufferUsages::VERTEX);
+        // Draws start at their own instance, which indirect draws only support with
+        // `INDIRECT_FIRST_INSTANCE`
+        let draws_buffer = device
+            .features()
+            .contains(Features::MULTI_DRAW_INDIRECT | Features::INDIRECT_FIRST_INSTANCE)
+            .then(|| GrowableBuffer::new(device, BufferUsages::INDIRECT));
+
+        let camera_buffer = Renderer::create_uniform_buffer(&context, size_of::<CameraUniform>());
+        let lights_buffer = Renderer::create_uniform_buffer(&context, size_of::<LightsUniform>());
+        let bind_group = Renderer::create_bind_group(
+            &context,
+            &bind_group_layout,
+            &camera_buffer,
+            &lights_buffer,
+        );
 
         Renderer {
             context,
-            render_pipeline,
+            texture_layout,
+            sampler,
+            pipelines,
+            white_texture,
+            textures: Vec::new(),
+            vertex_buffer,
+            index_buffer,
+            instance_buffer,
+            draws_buffer,
+            camera_buffer,
+            lights_buffer,
+            bind_group,
+            uploaded: None,
         }
     }
 
-    fn create_pipeline(context: &Context) -> RenderPipeline {
-        let device = &context.device;
+    fn create_texture_layout(context: &Context) -> BindGroupLayout {
+        let entries = &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+
+        context
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries,
+            })
+    }
 
-        let binding_type = BindingType::Buffer {
+    fn create_texture_bind_group(
+        context: &Context,
+        layout: &BindGroupLayout,
+        sampler: &Sampler,
+        image: &Image,
+    ) -> BindGroup {
+        let size = Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = context.device.create_texture(&TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        context.queue.write_texture(
+            texture.as_image_copy(),
+            &image.pixels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * image.width),
+                rows_per_image: Some(image.height),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        let entries = [
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(sampler),
+            },
+        ];
+
+        context.device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &entries,
+        })
+    }
+
+    /// Writes the frame into the persistent buffers and uploads textures added since the last
+    /// frame. The scene geometry is only written when it changed.
+    fn upload(&mut self, render_state: &RenderState) {
+        self.upload_textures(&render_state.textures);
+
+        let device = &self.context.device;
+        let queue = &self.context.queue;
+
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::bytes_of(&render_state.camera),
+        );
+        queue.write_buffer(
+            &self.lights_buffer,
+            0,
+            bytemuck::bytes_of(&render_state.lights),
+        );
+
+        let scene = &render_state.scene;
+        if self.uploaded == Some(scene.generation) {
+            let size = size_of::<Instance>();
+            for range in &render_state.instance_writes {
+                let instances = &scene.instances[range.start as usize..range.end as usize];
+                queue.write_buffer(
+                    self.instance_buffer.buffer(),
+                    (range.start as usize * size) as u64,
+                    bytemuck::cast_slice(instances),
+                );
+            }
+            return;
+        }
+        self.uploaded = Some(scene.generation);
+
+        let vertexes = bytemuck::cast_slice(&scene.vertexes);
+        self.vertex_buffer.write(device, queue, vertexes);
+        let indexes = bytemuck::cast_slice(&scene.indexes);
+        self.index_buffer.write(device, queue, indexes);
+        let instances = bytemuck::cast_slice(&scene.instances);
+        self.instance_buffer.write(device, queue, instances);
+
+        if let Some(draws_buffer) = &mut self.draws_buffer {
+            let draws: Vec<u8> = scene
+                .draws
+                .iter()
+                .flat_map(|draw| draw.as_bytes())
+                .copied()
+                .collect();
+            draws_buffer.write(device, queue, &draws);
+        }
+    }
+
+    /// Uploads the textures loaded since the last frame, textures are never unloaded.
+    fn upload_textures(&mut self, textures: &[Arc<Image>]) {
+        for image in &textures[self.textures.len().min(textures.len())..] {
+            let bind_group = Renderer::create_texture_bind_group(
+                &self.context,
+                &self.texture_layout,
+                &self.sampler,
+                image,
+            );
+            self.textures.push(bind_group);
+        }
+    }
+
+    fn create_bind_group_layout(context: &Context) -> BindGroupLayout {
+        let uniform = |size: usize| BindingType::Buffer {
             ty: BufferBindingType::Uniform,
             has_dynamic_offset: false,
-            min_binding_size: wgpu::BufferSize::new(64),
+            min_binding_size: wgpu::BufferSize::new(size as u64),
         };
 
-        let entries = &[BindGroupLayoutEntry {
-            binding: 0,
-            visibility: ShaderStages::VERTEX,
-            ty: binding_type,
-            count: None,
-        }];
+        let entries = &[
+            // Camera
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: uniform(size_of::<CameraUniform>()),
+                count: None,
+            },
+            // Lights
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: uniform(size_of::<LightsUniform>()),
+                count: None,
+            },
+        ];
 
-        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: None,
-            entries,
-        });
+        context
+            .device
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries,
+            })
+    }
+
+    /// All pipelines share the depth attachment, without `depth_test` shapes are drawn in
+    /// submission order and never write depth.
+    fn create_pipeline(
+        context: &Context,
+        bind_group_layouts: &[&BindGroupLayout],
+        topology: PrimitiveTopology,
+        depth_test: bool,
+    ) -> RenderPipeline {
+        let device = &context.device;
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts,
             push_constant_ranges: &[],
         });
 
         let wgsl = wgpu::include_wgsl!("./wgsl/shader.wgsl");
         let shader = device.create_shader_module(wgsl);
 
-        // Vertex Position
-        let vertex_attributes = [VertexAttribute {
-            format: wgpu::VertexFormat::Float32x4,
-            offset: 0,
-            shader_location: 0,
-        }];
+        // Vertex
+        let vertex_attributes = [
+            VertexAttribute {
+                format: wgpu::VertexFormat::Float32x4,
+                offset: 0, //bytes no offset to position
+                shader_location: 0,
+            },
+            VertexAttribute {
+                format: wgpu::VertexFormat::Float32x4,
+                offset: (4 * 4), //bytes offset to normal
+                shader_location: 1,
+            },
+            VertexAttribute {
+                format: wgpu::VertexFormat::Float32x4,
+                offset: 2 * (4 * 4), //bytes offset to color
+                shader_location: 8,
+            },
+            VertexAttribute {
+                format: wgpu::VertexFormat::Float32x2,
+                offset: 3 * (4 * 4), //bytes offset to uv
+                shader_location: 2,
+            },
+        ];
 
         // Instance
         let transform_attributes = [
             VertexAttribute {
                 format: wgpu::VertexFormat::Float32x4,
                 offset: 0, //bytes no offset to 1st row
-                shader_location: 1,
+                shader_location: 3,
             },
             VertexAttribute {
                 format: wgpu::VertexFormat::Float32x4,
                 offset: (4 * 4), //bytes offset to 2nd row
-                shader_location: 2,
+                shader_location: 4,
             },
             VertexAttribute {
                 format: wgpu::VertexFormat::Float32x4,
                 offset: 2 * (4 * 4), //bytes offset to 3rd row
-                shader_location: 3,
+                shader_location: 5,
             },
             VertexAttribute {
                 format: wgpu::VertexFormat::Float32x4,
                 offset: 3 * (4 * 4), //bytes offset to 4th row
-                shader_location: 4,
+                shader_location: 6,
             },
             VertexAttribute {
                 format: wgpu::VertexFormat::Float32x4,
-                offset: 4 * (4 * 4), //bytes offset to 4th row
-                shader_location: 5,
+                offset: 4 * (4 * 4), //bytes offset to color
+                shader_location: 7,
             },
         ];
 
         let vertex_buffer_layouts = [
             VertexBufferLayout {
-                array_stride: 4 * 4,
+                // byte size of Vec4(position) + Vec4(normal) + Vec4(color) + Vec2(uv) + Vec2(padding)
+                array_stride: 4 * (4 * 4),
                 step_mode: wgpu::VertexStepMode::Vertex,
                 attributes: &vertex_attributes,
             },
@@ -133,7 +462,12 @@ impl Renderer {
         let fragment_state = FragmentState {
             module: &shader,
             entry_point: "fs_main",
-            targets: &[Some(context.texture_format.into())],
+            // Opacity and translucent colors blend over what was drawn before
+            targets: &[Some(ColorTargetState {
+                format: context.texture_format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
         };
 
         let multisample_state = MultisampleState {
@@ -141,35 +475,52 @@ impl Renderer {
             ..Default::default()
         };
 
+        let depth_stencil_state = DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: depth_test,
+            // Strokes lie on the plane of their fill and are drawn after it, equal depths pass
+            depth_compare: if depth_test {
+                CompareFunction::LessEqual
+            } else {
+                CompareFunction::Always
+            },
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        };
+
         let descriptor = RenderPipelineDescriptor {
             label: None,
             layout: Some(&pipeline_layout),
             vertex: vertex_state,
             fragment: Some(fragment_state),
-            primitive: PrimitiveState::default(),
+            primitive: PrimitiveState {
+                topology,
+                ..Default::default()
+            },
             multisample: multisample_state,
-            depth_stencil: None,
+            depth_stencil: Some(depth_stencil_state),
             multiview: None,
         };
 
         device.create_render_pipeline(&descriptor)
     }
 
-    fn create_camera_bind_group(
+    fn create_bind_group(
         context: &Context,
         layout: &BindGroupLayout,
-        camera: Mat4,
+        camera_buffer: &Buffer,
+        lights_buffer: &Buffer,
     ) -> BindGroup {
-        let transform_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(camera.as_ref()),
-            usage: wgpu::BufferUsages::UNIFORM,
-        });
-
-        let entries = [BindGroupEntry {
-            binding: 0,
-            resource: transform_buffer.as_entire_binding(),
-        }];
+        let entries = [
+            BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: lights_buffer.as_entire_binding(),
+            },
+        ];
 
         context.device.create_bind_group(&BindGroupDescriptor {
             label: None,
@@ -178,80 +529,138 @@ impl Renderer {
         })
     }
 
-    pub fn render<'a>(&'a self, render_state: RenderState) {
-        let context = &self.context;
-
-        // Setup buffers and bind groups
-        let vertex_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
+    fn create_uniform_buffer(context: &Context, size: usize) -> Buffer {
+        context.device.create_buffer(&BufferDescriptor {
             label: None,
-            contents: bytemuck::cast_slice(&render_state.vertexes[..]),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+            size: size as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
 
-        let index_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&render_state.indexes[..]),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+    /// Serializes the scene built on `app` and renders it to the context target.
+    pub fn draw(&mut self, app: &mut App) -> Result<(), Error> {
+        self.render(app.state.serialize())
+    }
 
-        let instance_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&render_state.instances[..]),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+    /// Serializes the scene built on `app` and reads the rendered frame back.
+    pub fn screenshot(&mut self, app: &mut App) -> Result<Image, Error> {
+        self.capture(app.state.serialize())
+    }
 
-        let count = render_state.draws.len() as u32;
+    /// Renders to the context target. Frames are skipped without an error while the window is
+    /// minimized or its surface is being recreated.
+    pub fn render(&mut self, render_state: RenderState) -> Result<(), Error> {
+        self.upload(&render_state);
+        let context = &self.context;
 
-        let draws_buffer = context.device.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(
-                &render_state
-                    .draws
-                    .iter()
-                    .map(|x| x.as_bytes())
-                    .collect::<Vec<&[u8]>>()
-                    .concat()[..],
-            ),
-            usage: wgpu::BufferUsages::INDIRECT,
-        });
+        // Skipped frames try again right away, paused ones wait for the next resize
+        let Some(frame) = context.frame()? else {
+            if !context.paused {
+                context.request_redraw();
+            }
+            return Ok(());
+        };
+        let mut encoder = context
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor::default());
 
-        let camera_layout = self.render_pipeline.get_bind_group_layout(0);
-        let camera =
-            Renderer::create_camera_bind_group(&context, &camera_layout, render_state.camera);
+        self.encode(&mut encoder, &render_state, &frame.view);
 
-        // Start rendering phase
-        let surface_texture = context.surface.get_current_texture().unwrap();
-        let texture = &surface_texture.texture;
+        context.queue.submit(Some(encoder.finish()));
+        frame.present();
+        context.request_redraw();
+
+        Ok(())
+    }
+
+    /// Renders into an offscreen texture and returns the resolved frame as an RGBA image.
+    ///
+    /// Headless contexts render into their own target, windowed contexts into a temporary
+    /// texture so the swapchain is left untouched. Fails when the frame can't be read back,
+    /// like after the device is lost.
+    pub fn capture(&mut self, render_state: RenderState) -> Result<Image, Error> {
+        self.upload(&render_state);
+        let context = &self.context;
+
+        let temporary;
+        let texture = match &context.target {
+            Target::Offscreen { texture } => texture,
+            Target::Window { .. } => {
+                temporary = create_offscreen_texture(&context.device, &context.surface_config);
+                &temporary
+            }
+        };
         let view = texture.create_view(&TextureViewDescriptor::default());
 
-        let device = &context.device;
-        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+        let mut encoder = context
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor::default());
 
-        {
-            let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: None,
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &context.multisample_texture_view,
-                    resolve_target: Some(&view),
-                    ops: Operations {
-                        load: LoadOp::Clear(render_state.clear),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-
-            rpass.set_pipeline(&self.render_pipeline);
-            rpass.set_bind_group(0, &camera, &[]);
-
-            rpass.set_vertex_buffer(0, vertex_buffer.slice(..));
-            rpass.set_vertex_buffer(1, instance_buffer.slice(..));
-            rpass.set_index_buffer(index_buffer.slice(..), IndexFormat::Uint32);
-            rpass.multi_draw_indexed_indirect(&draws_buffer, 0, count);
-        }
+        self.encode(&mut encoder, &render_state, &view);
 
-        context.queue.submit(Some(encoder.finish()));
-        surface_texture.present();
-        context.window.request_redraw();
+        context.read_texture(encoder, texture)
+    }
+
+    fn encode(&self, encoder: &mut CommandEncoder, render_state: &RenderState, view: &TextureView) {
+        let context = &self.context;
+
+        // Start rendering phase
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &context.multisample_texture_view,
+                resolve_target: Some(view),
+                ops: Operations {
+                    load: LoadOp::Clear(context.clear.map_or(render_state.clear, Color::into)),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &context.depth_texture_view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(0));
+        rpass.set_vertex_buffer(1, self.instance_buffer.slice(0));
+        rpass.set_index_buffer(self.index_buffer.slice(0), IndexFormat::Uint32);
+
+        let scene = &render_state.scene;
+        for batch in &scene.batches {
+            rpass.set_pipeline(&self.pipelines[&(batch.primitive, render_state.depth_test)]);
+
+            let texture = match batch.texture {
+                Some(TextureId(i)) => &self.textures[i],
+                None => &self.white_texture,
+            };
+            rpass.set_bind_group(1, texture, &[]);
+
+            if let Some(draws_buffer) = &self.draws_buffer {
+                let offset = batch.draws.start as u64 * size_of::<DrawIndexedIndirect>() as u64;
+                let count = batch.draws.end - batch.draws.start;
+                rpass.multi_draw_indexed_indirect(draws_buffer.buffer(), offset, count);
+                continue;
+            }
+
+            let draws = &scene.draws[batch.draws.start as usize..batch.draws.end as usize];
+            for draw in draws {
+                // Offsetting the buffers stands in for the base vertex and instance, which not
+                // every adapter supports
+                let vertex_offset = draw.vertex_offset as u64 * size_of::<Vertex>() as u64;
+                let instance_offset = draw.base_instance as u64 * size_of::<Instance>() as u64;
+                rpass.set_vertex_buffer(0, self.vertex_buffer.slice(vertex_offset));
+                rpass.set_vertex_buffer(1, self.instance_buffer.slice(instance_offset));
+
+                let indexes = draw.base_index..draw.base_index + draw.vertex_count;
+                rpass.draw_indexed(indexes, 0, 0..draw.instance_count);
+            }
+        }
     }
 }
@@ -1,179 +1,827 @@
-use std::{cell::RefCell, f32::consts::PI, rc::Rc};
+use std::{
+    collections::HashMap,
+    error, fmt,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-use glam::{Mat4, Quat, Vec3, Vec4};
-use wgpu::util::DrawIndexedIndirect;
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+use wgpu::{util::DrawIndexedIndirect, PrimitiveTopology};
 
 use crate::{
+    app::Mesh,
     color::Color,
-    renderer::{Instance, RenderState},
+    geometry::{self, Geometry, Outline},
+    image::Image,
+    line::{self, View},
+    path,
+    renderer::{CameraUniform, Instance, LightUniform, LightsUniform, RenderState, Scene, Vertex},
 };
 
 #[derive(Default)]
 pub struct State {
-    pub shapes: Vec<Shape>,
-    pub nodes: Vec<Node>,
-    pub root: Rc<RefCell<Node>>,
+    /// Shapes by [`ShapeId`], `None` once removed.
+    pub shapes: Vec<Option<Shape>>,
+    /// Nodes by [`NodeId`], `None` once removed. The root is always the first.
+    pub nodes: Vec<Option<Node>>,
     pub camera: Camera,
     pub clear: Color,
+    pub depth_test: bool,
+    pub lights: Vec<Light>,
+    /// Images loaded with `App::load_texture`, indexed by [`TextureId`].
+    pub textures: Vec<Arc<Image>>,
+    /// Set by every change to the nodes or shapes other than a transform, color or visibility,
+    /// the next `serialize` rebuilds the scene.
+    pub(crate) dirty: bool,
+    pub(crate) scene: Option<Arc<Scene>>,
+    /// Geometry of the built-in shapes in the kept scene, generated once for each size and
+    /// resolution
+    pub(crate) geometry_cache: HashMap<GeometryKey, Arc<Geometry>>,
+    /// Size of the frame in pixels, lines and pixel shapes are built for it
+    pub(crate) viewport: Vec2,
+    /// The view the lines and pixel shapes of the kept scene were built for, `None` without any
+    pub(crate) screen_view: Option<View>,
+    /// Every node of the kept scene by [`NodeId`], `None` for the ones it doesn't have
+    pub(crate) layouts: Vec<Option<NodeLayout>>,
+    /// Nodes whose transform, color or visibility changed since the last `serialize`, and
+    /// whether the transform did. Only their subtrees' instances are serialized again.
+    pub(crate) moved_nodes: Vec<(NodeId, bool)>,
 }
 
-impl State {
-    pub fn serialize(&mut self) -> RenderState {
-        let mut node_stack = vec![self.root.clone()];
-        let mut transform_index_stack: Vec<usize> = vec![];
-
-        let mut vertexes: Vec<Vec4> = Vec::new();
-        let mut indexes: Vec<u32> = Vec::new();
-        let mut instances: Vec<Instance> = vec![Instance::default()];
-        let mut draws: Vec<DrawIndexedIndirect> = vec![DrawIndexedIndirect::default()];
-
-        loop {
-            let curr_transform_index = transform_index_stack.pop();
-            let Some(curr) = node_stack.pop() else {
-                break;
-            };
-            let curr = curr.borrow_mut();
+/// Handle to a node returned by `App::push`.
+///
+/// Handles stay valid until the node is removed or the scene is cleared.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(pub(crate) usize);
 
-            let mut instance = Instance::default();
+impl NodeId {
+    /// The node every scene starts with, shapes added before any `push` belong to it.
+    pub const ROOT: NodeId = NodeId(0);
+}
 
-            if let Some(transform) = &curr.transform {
-                if let Some(scale) = transform.scale {
-                    instance.transform = instance.transform.mul_mat4(&Mat4::from_scale(scale));
-                };
+/// Handle to a shape returned by the shape methods of `App`.
+///
+/// Handles stay valid until the shape is removed or the scene is cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShapeId(pub(crate) usize);
 
-                if let Some(rotation) = transform.rotation {
-                    instance.transform = instance.transform.mul_mat4(&Mat4::from_quat(rotation));
-                };
+/// Why a change through a node or shape handle was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneError {
+    /// The node was removed, or belongs to a cleared scene.
+    UnknownNode(NodeId),
+    /// The shape was removed, or belongs to a cleared scene.
+    UnknownShape(ShapeId),
+    /// The root can't be removed or moved.
+    Root,
+    /// A node can't be moved under itself or one of its children.
+    Cycle,
+}
 
-                if let Some(translation) = transform.translation {
-                    instance.transform = instance
-                        .transform
-                        .mul_mat4(&Mat4::from_translation(translation));
-                };
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::UnknownNode(NodeId(id)) => write!(f, "node {id} doesn't exist"),
+            SceneError::UnknownShape(ShapeId(id)) => write!(f, "shape {id} doesn't exist"),
+            SceneError::Root => write!(f, "the root node can't be removed or moved"),
+            SceneError::Cycle => write!(f, "a node can't be moved under its own subtree"),
+        }
+    }
+}
 
-                if let Some(i) = curr_transform_index {
-                    instance.transform = instances[i].transform.mul_mat4(&instance.transform);
-                };
+impl error::Error for SceneError {}
+
+/// Source of [`Scene::generation`], shared by every `State` so a renderer never confuses
+/// the scenes of two apps.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Scenes can be built and serialized on other threads
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<State>();
+    assert_send_sync::<RenderState>();
+    assert_send_sync::<crate::app::App>();
+};
+
+/// Handle to a texture loaded with `App::load_texture`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureId(pub(crate) usize);
+
+/// A run of consecutive draws sharing the same texture and pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    pub(crate) texture: Option<TextureId>,
+    pub(crate) primitive: PrimitiveTopology,
+    pub(crate) draws: Range<u32>,
+}
+
+/// Geometry slot, texture and topology shared by every instance of a draw.
+type DrawKey = (usize, Option<TextureId>, PrimitiveTopology);
+
+/// Instances that end up in a single draw.
+struct DrawGroup {
+    key: DrawKey,
+    instances: Vec<Instance>,
+    /// Node and source of each run of `instances`
+    owners: Vec<(Range<u32>, NodeId, InstanceSource)>,
+}
+
+impl DrawGroup {
+    fn push(
+        &mut self,
+        owner: NodeId,
+        source: InstanceSource,
+        layout: &NodeLayout,
+        shapes: &[Option<Shape>],
+    ) {
+        let start = self.instances.len() as u32;
+        self.instances.extend(source.instances(layout, shapes));
+        let end = self.instances.len() as u32;
+        self.owners.push((start..end, owner, source));
+    }
+}
+
+/// The group `key` is added to. With depth testing the draw order doesn't matter and every
+/// copy of a geometry can share one draw, painter's order only merges consecutive copies.
+fn draw_group<'a>(
+    groups: &'a mut Vec<DrawGroup>,
+    group_indexes: &mut HashMap<DrawKey, usize>,
+    depth_test: bool,
+    key: DrawKey,
+) -> &'a mut DrawGroup {
+    let group = if depth_test {
+        group_indexes.get(&key).copied()
+    } else {
+        groups
+            .last()
+            .filter(|group| group.key == key)
+            .map(|_| groups.len() - 1)
+    };
+
+    match group {
+        Some(group) => &mut groups[group],
+        None => {
+            group_indexes.insert(key, groups.len());
+            groups.push(DrawGroup {
+                key,
+                instances: vec![],
+                owners: vec![],
+            });
+            groups.last_mut().unwrap()
+        }
+    }
+}
+
+/// Fill and stroke of a node once the values it inherits are resolved.
+#[derive(Clone, Copy)]
+struct NodeStyle {
+    fill: bool,
+    stroke: Stroke,
+    stroke_width: LineWidth,
+}
+
+impl Default for NodeStyle {
+    fn default() -> Self {
+        Self {
+            fill: true,
+            stroke: Stroke::None,
+            stroke_width: LineStyle::default().width,
+        }
+    }
+}
+
+/// Where instances of the kept scene come from, so they can be recomputed when only the
+/// transform, color or visibility of their node changed.
+#[derive(Debug, Clone, Copy)]
+enum InstanceSource {
+    /// The node's own transform and color
+    Node,
+    /// Every copy of a [`Shape::Instances`], by shape index
+    Copies { shape: usize },
+    /// Vertices built for the view, already in world space
+    World,
+    /// A point, in the stroke color when there is one
+    Point,
+    /// The outlines of a shape, in the stroke color
+    Stroke,
+}
+
+impl InstanceSource {
+    /// The instances drawn for the source, one for each copy or a single one otherwise.
+    fn instances<'a>(
+        self,
+        layout: &'a NodeLayout,
+        shapes: &'a [Option<Shape>],
+    ) -> impl Iterator<Item = Instance> + 'a {
+        let node = layout.instance;
+        let hidden = layout.hidden;
+        // Collapses every vertex to a point, so nothing is rasterized
+        let visible = move |instance: Instance| {
+            if hidden {
+                Instance {
+                    transform: Mat4::ZERO,
+                    color: Vec4::ZERO,
+                }
+            } else {
+                instance
             }
+        };
 
-            instance.color = match curr.color {
-                Some(color) => color.into(),
-                None => match curr_transform_index {
-                    Some(i) => instances[i].color,
-                    None => instance.color,
-                },
+        let stroke = match layout.style.stroke {
+            Stroke::Color(color) => color.into(),
+            Stroke::None => node.color,
+        };
+        let single = match self {
+            InstanceSource::Node => Some(node),
+            InstanceSource::Copies { .. } => None,
+            InstanceSource::World => Some(Instance {
+                transform: Mat4::IDENTITY,
+                ..node
+            }),
+            InstanceSource::Point | InstanceSource::Stroke => Some(Instance {
+                transform: Mat4::IDENTITY,
+                color: stroke,
+            }),
+        };
+
+        let copies: &[InstanceData] = match self {
+            InstanceSource::Copies { shape } => match shapes.get(shape) {
+                Some(Some(Shape::Instances(_, copies))) => copies,
+                _ => &[],
+            },
+            _ => &[],
+        };
+        let copies = copies.iter().map(move |copy| {
+            let mut transform = node.transform.mul_mat4(&copy.transform.matrix());
+            if let Some(size) = copy.size {
+                transform = transform.mul_mat4(&Mat4::from_scale(Vec3::splat(size)));
+            }
+
+            let mut color = copy.color.map_or(node.color, Vec4::from);
+            if let Some(opacity) = copy.opacity {
+                color.w *= opacity;
+            }
+
+            Instance { transform, color }
+        });
+
+        single.into_iter().chain(copies).map(visible)
+    }
+}
+
+/// A node of the kept scene with everything it inherits resolved, and the instances drawn for
+/// it.
+#[derive(Clone)]
+pub(crate) struct NodeLayout {
+    instance: Instance,
+    style: NodeStyle,
+    hidden: bool,
+    texture: Option<TextureId>,
+    /// Whether any of its vertices were built for the view, they depend on its transform
+    view_built: bool,
+    /// Ranges of [`Scene::instances`], one for each source
+    instances: Vec<(Range<u32>, InstanceSource)>,
+}
+
+impl NodeLayout {
+    fn new(node: &Node, parent: Option<&NodeLayout>) -> NodeLayout {
+        let mut instance = Instance::default();
+
+        if let Some(transform) = &node.transform {
+            instance.transform = transform.matrix();
+
+            if let Some(parent) = parent {
+                instance.transform = parent.instance.transform.mul_mat4(&instance.transform);
             };
+        }
 
-            instances.push(instance);
+        instance.color = match (node.color, parent) {
+            (Some(color), _) => color.into(),
+            (None, Some(parent)) => parent.instance.color,
+            (None, None) => instance.color,
+        };
 
-            for i in &curr.shapes {
-                let shape = &self.shapes[*i];
+        let parent_style = parent.map_or_else(NodeStyle::default, |parent| parent.style);
+        let style = NodeStyle {
+            fill: node.fill.unwrap_or(parent_style.fill),
+            stroke: node.stroke.unwrap_or(parent_style.stroke),
+            stroke_width: node.stroke_width.unwrap_or(parent_style.stroke_width),
+        };
 
-                let (mut vx, mut ix) = match shape {
-                    Shape::Triangle(a, b, c) => {
-                        let a = Vec4::from((a[0], a[1], a[2], 1.));
-                        let b = Vec4::from((b[0], b[1], b[2], 1.));
-                        let c = Vec4::from((c[0], c[1], c[2], 1.));
+        NodeLayout {
+            instance,
+            style,
+            hidden: node.hidden || parent.is_some_and(|parent| parent.hidden),
+            texture: node.texture.or(parent.and_then(|parent| parent.texture)),
+            view_built: false,
+            instances: vec![],
+        }
+    }
+}
 
-                        (vec![a, b, c], vec![0, 1, 2])
-                    }
-                    Shape::Square => {
-                        let l = f32::sqrt(1. / 8.);
-                        let a = Vec4::from((l, l, 0., 1.));
-                        let b = Vec4::from((l, -l, 0., 1.));
-                        let c = Vec4::from((-l, l, 0., 1.));
-                        let d = Vec4::from((-l, -l, 0., 1.));
-
-                        (vec![a, b, c, d], vec![0, 1, 2, 1, 2, 3])
-                    }
-                    Shape::Polygon(s) => {
-                        let vx = (0..*s)
-                            .map(|i| (i as f32 / *s as f32) * 2. * PI)
-                            .map(|th| [f32::cos(th), f32::sin(th)])
-                            .map(|[x, y]| Vec4::new(x / 2., y / 2., 0., 1.))
-                            .collect();
+/// Vertices and indexes of a scene, with a draw without instances for each appended geometry.
+#[derive(Default)]
+struct GeometryBuffers {
+    vertexes: Vec<Vertex>,
+    indexes: Vec<u32>,
+    draws: Vec<DrawIndexedIndirect>,
+}
 
-                        let ix = (0..s - 2).flat_map(|i| [0, i + 1, i + 2]).collect();
+impl GeometryBuffers {
+    /// Appends `geometry` and returns its slot in `draws`.
+    fn append(&mut self, (vx, ix): &Geometry) -> usize {
+        self.draws.push(DrawIndexedIndirect {
+            vertex_count: ix.len() as u32,
+            instance_count: 0,
+            base_index: self.indexes.len() as u32,
+            vertex_offset: self.vertexes.len() as i32,
+            base_instance: 0,
+        });
+        self.vertexes.extend_from_slice(vx);
+        self.indexes.extend_from_slice(ix);
 
-                        (vx, ix)
-                    }
-                    Shape::Mesh(vx, tp) => {
-                        let vx: Vec<Vec4> = vx.iter().map(|x| (*x, 1.0).into()).collect();
+        self.draws.len() - 1
+    }
+}
 
-                        match tp {
-                            Topology::TriangleList => {
-                                //TODO: CHECK NUMBER OF VERTICES AND PANIC
-                                let ix = (0..(vx.len() as u32)).collect();
+impl State {
+    /// Like [`State::serialize`] but fails on the first invalid shape instead of skipping it.
+    pub fn try_serialize(&mut self) -> Result<RenderState, ShapeError> {
+        for shape in self.shapes.iter().flatten() {
+            shape.validate()?;
+        }
 
-                                (vx, ix)
-                            }
-                            Topology::TriangleStrip => {
-                                //TODO: CHECK NUMBER OF VERTICES AND PANIC
-                                let n = vx.len() as u32;
+        Ok(self.serialize())
+    }
+
+    /// Flattens the node tree into buffers for the renderer, invalid shapes are left out.
+    ///
+    /// The flattened tree is kept and reused until the nodes or shapes change, or until the
+    /// camera or viewport change when it has lines or shapes given in pixels.
+    pub fn serialize(&mut self) -> RenderState {
+        let view = View::new(&self.camera, self.viewport);
+        let stale = self.screen_view.is_some_and(|built| built != view);
 
-                                let ix = (0..n - 2).flat_map(|i| [i, i + 1, i + 2]).collect();
+        let mut instance_writes = vec![];
+        let mut rebuild = self.dirty || stale || self.scene.is_none();
+        if !rebuild && !self.moved_nodes.is_empty() {
+            match self.serialize_instances() {
+                Some(ranges) => instance_writes = ranges,
+                None => rebuild = true,
+            }
+        }
+        if rebuild {
+            self.scene = Some(Arc::new(self.serialize_scene()));
+            self.dirty = false;
+            instance_writes.clear();
+        }
+        self.moved_nodes.clear();
+        let scene = self.scene.clone().unwrap();
+
+        let camera = CameraUniform {
+            matrix: self.camera.matrix(),
+            eye: (self.camera.view.eye, 1.).into(),
+        };
+
+        let clear = self.clear.into();
+
+        RenderState {
+            scene,
+            textures: self.textures.clone(),
+            clear,
+            camera,
+            lights: self.lights_uniform(),
+            depth_test: self.depth_test,
+            instance_writes,
+        }
+    }
 
-                                (vx, ix)
+    fn serialize_scene(&mut self) -> Scene {
+        let mut cache = std::mem::take(&mut self.geometry_cache);
+        let view = View::new(&self.camera, self.viewport);
+        self.screen_view = None;
+
+        let mut layouts: Vec<Option<NodeLayout>> = vec![None; self.nodes.len()];
+        let mut node_stack = vec![NodeId::ROOT];
+
+        let mut buffers = GeometryBuffers::default();
+        // Where each cached geometry landed in the buffers for this scene
+        let mut slots: HashMap<GeometryKey, usize> = HashMap::new();
+        let mut groups: Vec<DrawGroup> = vec![];
+        let mut group_indexes: HashMap<DrawKey, usize> = HashMap::new();
+
+        // Parents are always visited before their children, so their layout is ready
+        while let Some(id) = node_stack.pop() {
+            let Some(Some(curr)) = self.nodes.get(id.0) else {
+                continue;
+            };
+
+            let parent = curr
+                .parent
+                .and_then(|parent| layouts.get(parent.0)?.as_ref());
+            let mut layout = NodeLayout::new(curr, parent);
+            let (instance, style) = (layout.instance, layout.style);
+
+            // Hidden nodes keep their place in the scene, with instances that draw nothing
+            for i in &curr.shapes {
+                let Some(shape) = &self.shapes[*i] else {
+                    continue;
+                };
+                if shape.validate().is_err() {
+                    continue;
+                }
+
+                // Only flat shapes have outlines, and only they can go without their fill
+                if style.fill || !shape.has_outline() {
+                    let slot = match shape.geometry_key() {
+                        Some(key) => *slots.entry(key).or_insert_with(|| {
+                            let geometry = cache
+                                .entry(key)
+                                .or_insert_with(|| Arc::new(shape.geometry()));
+                            buffers.append(geometry)
+                        }),
+                        None => match shape {
+                            Shape::Line(points, closed, line_style) => {
+                                layout.view_built = true;
+                                let points: Vec<Vec3> = points
+                                    .iter()
+                                    .map(|&point| instance.transform.transform_point3(point))
+                                    .collect();
+                                buffers.append(&line::line(&points, *closed, line_style, &view))
                             }
+                            Shape::Path(pixels, _) => {
+                                layout.view_built = true;
+                                let points: Vec<Vec3> = view
+                                    .pixels_to_world(pixels)
+                                    .into_iter()
+                                    .map(|point| instance.transform.transform_point3(point))
+                                    .collect();
+                                let normal = instance
+                                    .transform
+                                    .transform_vector3(geometry::FLAT_NORMAL)
+                                    .normalize_or_zero();
+                                buffers.append(&path::fill(pixels, &points, normal))
+                            }
+                            Shape::Point(pixel) => {
+                                layout.view_built = true;
+                                let point = instance
+                                    .transform
+                                    .transform_point3(view.pixels_to_world(&[*pixel])[0]);
+                                buffers.append(&line::dot(point, style.stroke_width, &view))
+                            }
+                            _ => buffers.append(&shape.geometry()),
+                        },
+                    };
+
+                    let key = (slot, layout.texture, shape.primitive());
+                    let group = draw_group(&mut groups, &mut group_indexes, self.depth_test, key);
+
+                    match shape {
+                        Shape::Instances(..) => {
+                            let source = InstanceSource::Copies { shape: *i };
+                            group.push(id, source, &layout, &self.shapes)
+                        }
+                        Shape::Line(..) | Shape::Path(..) => {
+                            group.push(id, InstanceSource::World, &layout, &self.shapes)
+                        }
+                        Shape::Point(_) => {
+                            group.push(id, InstanceSource::Point, &layout, &self.shapes)
                         }
+                        _ => group.push(id, InstanceSource::Node, &layout, &self.shapes),
                     }
-                };
+                }
+
+                if let Stroke::Color(_) = style.stroke {
+                    if !style.stroke_width.is_valid() {
+                        continue;
+                    }
+                    let Some(outlines) = shape.outlines(&view) else {
+                        continue;
+                    };
+                    layout.view_built = true;
 
-                draws.push(DrawIndexedIndirect {
-                    vertex_count: ix.len() as u32,
-                    instance_count: 1,
-                    base_index: indexes.len() as u32,
-                    vertex_offset: vertexes.len() as i32,
-                    base_instance: (instances.len() - 1) as u32,
-                });
+                    let outlines: Vec<Outline> = outlines
+                        .into_iter()
+                        .map(|(points, closed)| {
+                            let points = points
+                                .into_iter()
+                                .map(|point| instance.transform.transform_point3(point))
+                                .collect();
+                            (points, closed)
+                        })
+                        .collect();
+                    let line_style = LineStyle {
+                        width: style.stroke_width,
+                        ..Default::default()
+                    };
+                    let slot = buffers.append(&line::outlines(&outlines, &line_style, &view));
 
-                vertexes.append(&mut vx);
-                indexes.append(&mut ix);
+                    // Drawn over the fill, untextured
+                    let key = (slot, None, PrimitiveTopology::TriangleList);
+                    let group = draw_group(&mut groups, &mut group_indexes, self.depth_test, key);
+                    group.push(id, InstanceSource::Stroke, &layout, &self.shapes);
+                }
             }
 
-            node_stack.append(&mut curr.children.iter().rev().map(|x| x.clone()).collect());
-            transform_index_stack.append(&mut vec![instances.len() - 1; curr.children.len()]);
+            if layout.view_built {
+                self.screen_view = Some(view);
+            }
+            layouts[id.0] = Some(layout);
+            node_stack.extend(curr.children.iter().rev());
         }
 
-        let camera_view = Mat4::look_at_lh(
-            self.camera.view.eye,
-            self.camera.view.center,
-            self.camera.view.up,
-        );
-        let camera_projection = match self.camera.projection {
-            CameraProjection::Perspective {
-                fov_y_radians,
-                aspect_ratio,
-                z_near,
-                z_far,
-            } => Mat4::perspective_lh(fov_y_radians, aspect_ratio, z_near, z_far),
-            CameraProjection::Orthographic {
-                left,
-                right,
-                bottom,
-                top,
-                near,
-                far,
-            } => Mat4::orthographic_lh(left, right, bottom, top, near, far),
-        };
+        // Geometry no longer drawn is dropped, sizes that change every frame would pile up
+        cache.retain(|key, _| slots.contains_key(key));
+        self.geometry_cache = cache;
 
-        let camera = camera_projection.mul_mat4(&camera_view);
+        let mut instances: Vec<Instance> = vec![];
+        let mut draws: Vec<DrawIndexedIndirect> = vec![];
+        let mut batches: Vec<Batch> = vec![];
 
-        let clear = wgpu::Color {
-            r: 1.0,
-            g: 1.0,
-            b: 1.0,
-            a: 1.0,
-        };
+        for group in groups {
+            let (slot, texture, primitive) = group.key;
+
+            let base = instances.len() as u32;
+            for (range, owner, source) in group.owners {
+                if let Some(Some(layout)) = layouts.get_mut(owner.0) {
+                    layout
+                        .instances
+                        .push((base + range.start..base + range.end, source));
+                }
+            }
+
+            draws.push(DrawIndexedIndirect {
+                instance_count: group.instances.len() as u32,
+                base_instance: instances.len() as u32,
+                ..buffers.draws[slot]
+            });
+            instances.extend(group.instances);
 
-        return RenderState {
-            vertexes,
-            indexes,
+            let draw = (draws.len() - 1) as u32;
+            match batches.last_mut() {
+                Some(batch) if batch.texture == texture && batch.primitive == primitive => {
+                    batch.draws.end = draw + 1
+                }
+                _ => batches.push(Batch {
+                    texture,
+                    primitive,
+                    draws: draw..draw + 1,
+                }),
+            }
+        }
+
+        self.layouts = layouts;
+
+        Scene {
+            vertexes: buffers.vertexes,
+            indexes: buffers.indexes,
             instances,
             draws,
-            clear,
-            camera,
-        };
+            batches,
+            generation: GENERATION.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Recomputes the instances of every subtree in `moved_nodes` inside the kept scene, and
+    /// returns the ranges of [`Scene::instances`] that changed. `None` when the scene has to be
+    /// rebuilt instead, because a moved subtree has vertices built for the view.
+    fn serialize_instances(&mut self) -> Option<Vec<Range<u32>>> {
+        let scene = Arc::make_mut(self.scene.as_mut()?);
+        let mut changed: Vec<Range<u32>> = vec![];
+
+        for &(id, moved) in &self.moved_nodes {
+            let mut stack = vec![id];
+            while let Some(id) = stack.pop() {
+                let Some(Some(node)) = self.nodes.get(id.0) else {
+                    continue;
+                };
+
+                let parent = node
+                    .parent
+                    .and_then(|parent| self.layouts.get(parent.0)?.as_ref());
+                let resolved = NodeLayout::new(node, parent);
+                let layout = self.layouts.get_mut(id.0)?.as_mut()?;
+                if moved && layout.view_built {
+                    return None;
+                }
+
+                layout.instance = resolved.instance;
+                layout.style = resolved.style;
+                layout.hidden = resolved.hidden;
+
+                for (range, source) in &layout.instances {
+                    let instances = scene
+                        .instances
+                        .get_mut(range.start as usize..range.end as usize)?;
+                    for (instance, updated) in instances
+                        .iter_mut()
+                        .zip(source.instances(layout, &self.shapes))
+                    {
+                        *instance = updated;
+                    }
+                    changed.push(range.clone());
+                }
+
+                stack.extend(&node.children);
+            }
+        }
+
+        changed.sort_unstable_by_key(|range| range.start);
+
+        let mut ranges: Vec<Range<u32>> = vec![];
+        for range in changed.into_iter().filter(|range| !range.is_empty()) {
+            match ranges.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => ranges.push(range),
+            }
+        }
+
+        Some(ranges)
+    }
+
+    /// Drops every node and shape, leaving an empty root.
+    pub(crate) fn clear_tree(&mut self) {
+        self.shapes.clear();
+        self.nodes = vec![Some(Node::default())];
+        self.dirty = true;
+    }
+
+    pub(crate) fn node(&self, id: NodeId) -> Result<&Node, SceneError> {
+        match self.nodes.get(id.0) {
+            Some(Some(node)) => Ok(node),
+            _ => Err(SceneError::UnknownNode(id)),
+        }
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> Result<&mut Node, SceneError> {
+        match self.nodes.get_mut(id.0) {
+            Some(Some(node)) => Ok(node),
+            _ => Err(SceneError::UnknownNode(id)),
+        }
+    }
+
+    /// Adds a child to `parent` and returns its handle. Children of removed nodes are never
+    /// drawn.
+    pub(crate) fn add_node(&mut self, parent: NodeId, mut node: Node) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        node.parent = Some(parent);
+
+        self.nodes.push(Some(node));
+        if let Ok(parent) = self.node_mut(parent) {
+            parent.children.push(id);
+        }
+        self.dirty = true;
+
+        id
+    }
+
+    pub(crate) fn add_shape(&mut self, node: NodeId, shape: Shape) -> ShapeId {
+        let id = ShapeId(self.shapes.len());
+
+        self.shapes.push(Some(shape));
+        if let Ok(node) = self.node_mut(node) {
+            node.shapes.push(id.0);
+        }
+        self.dirty = true;
+
+        id
+    }
+
+    /// Changes a node through `f`, marking the scene for serialization.
+    pub(crate) fn update_node(
+        &mut self,
+        id: NodeId,
+        f: impl FnOnce(&mut Node),
+    ) -> Result<(), SceneError> {
+        f(self.node_mut(id)?);
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Changes the transform, color or visibility of a node through `f`. Only the instances of
+    /// its subtree are serialized again, unless `moved` changes a transform that vertices built
+    /// for the view depend on.
+    pub(crate) fn update_instances(
+        &mut self,
+        id: NodeId,
+        moved: bool,
+        f: impl FnOnce(&mut Node),
+    ) -> Result<(), SceneError> {
+        f(self.node_mut(id)?);
+        self.moved_nodes.push((id, moved));
+        Ok(())
+    }
+
+    /// Moves `id` with its subtree under `parent`, keeping its local transform.
+    pub(crate) fn reparent(&mut self, id: NodeId, parent: NodeId) -> Result<(), SceneError> {
+        if id == NodeId::ROOT {
+            return Err(SceneError::Root);
+        }
+        self.node(id)?;
+
+        let mut ancestor = Some(parent);
+        while let Some(current) = ancestor {
+            if current == id {
+                return Err(SceneError::Cycle);
+            }
+            ancestor = self.node(current)?.parent;
+        }
+
+        self.detach(id)?;
+        self.node_mut(id)?.parent = Some(parent);
+        self.node_mut(parent)?.children.push(id);
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    /// Removes `id` with its subtree and shapes.
+    pub(crate) fn remove(&mut self, id: NodeId) -> Result<(), SceneError> {
+        if id == NodeId::ROOT {
+            return Err(SceneError::Root);
+        }
+        self.detach(id)?;
+
+        let mut removed = vec![id];
+        while let Some(id) = removed.pop() {
+            let Some(node) = self.nodes[id.0].take() else {
+                continue;
+            };
+            for shape in node.shapes {
+                self.shapes[shape] = None;
+            }
+            removed.extend(node.children);
+        }
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    pub(crate) fn shape(&self, id: ShapeId) -> Result<&Shape, SceneError> {
+        match self.shapes.get(id.0) {
+            Some(Some(shape)) => Ok(shape),
+            _ => Err(SceneError::UnknownShape(id)),
+        }
+    }
+
+    pub(crate) fn remove_shape(&mut self, id: ShapeId) -> Result<(), SceneError> {
+        match self.shapes.get_mut(id.0) {
+            Some(shape @ Some(_)) => *shape = None,
+            _ => return Err(SceneError::UnknownShape(id)),
+        }
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    /// Takes `id` out of its parent's children.
+    fn detach(&mut self, id: NodeId) -> Result<(), SceneError> {
+        let parent = self.node(id)?.parent.ok_or(SceneError::Root)?;
+        if let Ok(parent) = self.node_mut(parent) {
+            parent.children.retain(|&child| child != id);
+        }
+
+        Ok(())
+    }
+
+    fn lights_uniform(&self) -> LightsUniform {
+        let mut uniform = LightsUniform::default();
+
+        if self.lights.is_empty() {
+            return uniform;
+        }
+        uniform.counts[0] = 1;
+
+        for light in &self.lights {
+            match *light {
+                Light::Ambient(color) => uniform.ambient += Vec4::from(color),
+                Light::Directional { direction, color } => {
+                    let count = &mut uniform.counts[1];
+                    if let Some(slot) = uniform.directional.get_mut(*count as usize) {
+                        *slot = LightUniform {
+                            vector: (direction.normalize_or_zero(), 0.).into(),
+                            color: color.into(),
+                        };
+                        *count += 1;
+                    }
+                }
+                Light::Point { position, color } => {
+                    let count = &mut uniform.counts[2];
+                    if let Some(slot) = uniform.point.get_mut(*count as usize) {
+                        *slot = LightUniform {
+                            vector: (position, 1.).into(),
+                            color: color.into(),
+                        };
+                        *count += 1;
+                    }
+                }
+            }
+        }
+
+        uniform
     }
 }
 
@@ -181,12 +829,353 @@ pub enum Shape {
     Triangle(Vec3, Vec3, Vec3),
     Square,
     Polygon(u32),
-    Mesh(Vec<Vec3>, Topology),
+    Mesh(Mesh, Topology),
+    Cube,
+    Sphere(u32),
+    Icosphere(u32),
+    Cylinder(u32),
+    Cone(u32),
+    Torus(u32, f32),
+    Plane(u32),
+    /// One shape drawn at every copy, see `App::instances`.
+    Instances(Box<Shape>, Vec<InstanceData>),
+    /// A line through the points, back to the first one when closed, see `App::polyline`.
+    Line(Vec<Vec3>, bool, LineStyle),
+    /// A flat shape through points in pixels from the top left corner of the window, see
+    /// `App::rect`. Always filled as if closed, the outline is only closed when set.
+    Path(Vec<Vec2>, bool),
+    /// A dot the size of the stroke width at a point in pixels, see `App::point`.
+    Point(Vec2),
+}
+
+/// How the ends of an arc are connected, see `App::arc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArcMode {
+    /// Filled like a chord, the outline only follows the curve.
+    Open,
+    /// Closed by a straight line between the ends.
+    Chord,
+    /// Closed through the center, like a slice of pie.
+    Pie,
+}
+
+/// How wide lines are drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineWidth {
+    /// Width in world units, lines get thinner with distance like other shapes.
+    World(f32),
+    /// Width in pixels, the same at any distance.
+    Screen(f32),
+}
+
+impl LineWidth {
+    /// Whether the width is finite and not negative.
+    pub(crate) fn is_valid(&self) -> bool {
+        let (LineWidth::World(width) | LineWidth::Screen(width)) = *self;
+        width.is_finite() && width >= 0.
+    }
+}
+
+/// How two segments of a line are joined.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineJoin {
+    /// Extends the outer edges until they meet, sharp turns are beveled instead.
+    #[default]
+    Miter,
+    Round,
+    /// Cuts the corner between the outer edges.
+    Bevel,
+}
+
+/// How the ends of an open line are drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineCap {
+    /// Ends exactly at the end point.
+    #[default]
+    Butt,
+    /// Extends past the end point by half the width.
+    Square,
+    Round,
+}
+
+/// Whether and in which color shapes are outlined, see `App::stroke`.
+#[derive(Clone, Copy, Default)]
+pub enum Stroke {
+    /// No outline, the default.
+    #[default]
+    None,
+    Color(Color),
+}
+
+/// Width, joins and caps of a line, set with `App::line_width`, `App::line_join` and
+/// `App::line_cap`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineStyle {
+    pub width: LineWidth,
+    pub join: LineJoin,
+    pub cap: LineCap,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        Self {
+            width: LineWidth::Screen(1.),
+            join: LineJoin::default(),
+            cap: LineCap::default(),
+        }
+    }
+}
+
+/// One copy of a shape drawn by `App::instances`, placed relative to the current node.
+#[derive(Default)]
+pub struct InstanceData {
+    pub transform: Transform,
+    /// `None` keeps the node color.
+    pub color: Option<Color>,
+    /// Scales the shape uniformly before `transform`, so markers can be sized without
+    /// building a scale for each. `None` keeps the shape's size.
+    pub size: Option<f32>,
+    /// Multiplies the alpha of the color, blending the copy over what was drawn before it.
+    /// `None` keeps it.
+    pub opacity: Option<f32>,
+}
+
+/// Why a shape can't be drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeError {
+    /// Fewer vertices, or indexes when given, than one primitive of the topology needs.
+    TooFewVertices { topology: Topology, count: usize },
+    /// A list topology with a count that isn't a multiple of its primitive size.
+    VertexCount { topology: Topology, count: usize },
+    /// An index pointing past the last vertex.
+    IndexOutOfRange { index: u32, vertices: usize },
+    /// A vertex with a NaN or infinite position or attribute.
+    NonFinite { vertex: usize },
+    /// A polygon with fewer than three sides.
+    Polygon { sides: u32 },
+    /// A torus with a NaN or infinite thickness.
+    Thickness,
+    /// Instances of a shape that can't be instanced, lines, shapes given in pixels or shapes
+    /// that are instanced themselves.
+    NotInstanceable,
+    /// A line or a shape given in pixels with fewer than two points.
+    TooFewPoints { count: usize },
+    /// A line with a negative, NaN or infinite width.
+    Width,
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShapeError::TooFewVertices { topology, count } => write!(
+                f,
+                "{topology:?} needs at least {} vertices, got {count}",
+                topology.vertices()
+            ),
+            ShapeError::VertexCount { topology, count } => write!(
+                f,
+                "{topology:?} needs a multiple of {} vertices, got {count}",
+                topology.vertices()
+            ),
+            ShapeError::IndexOutOfRange { index, vertices } => {
+                write!(f, "index {index} out of range for {vertices} vertices")
+            }
+            ShapeError::NonFinite { vertex } => {
+                write!(f, "vertex {vertex} has a NaN or infinite value")
+            }
+            ShapeError::Polygon { sides } => {
+                write!(f, "polygon needs at least 3 sides, got {sides}")
+            }
+            ShapeError::Thickness => write!(f, "torus thickness is NaN or infinite"),
+            ShapeError::NotInstanceable => {
+                write!(
+                    f,
+                    "lines, pixel shapes and instanced shapes can't be instanced"
+                )
+            }
+            ShapeError::TooFewPoints { count } => {
+                write!(f, "line or path needs at least 2 points, got {count}")
+            }
+            ShapeError::Width => write!(f, "line width is negative, NaN or infinite"),
+        }
+    }
+}
+
+impl error::Error for ShapeError {}
+
+impl Shape {
+    /// Checks that the shape has enough vertices, indexes in range and only finite values.
+    pub fn validate(&self) -> Result<(), ShapeError> {
+        match self {
+            Shape::Triangle(a, b, c) => match [a, b, c].iter().position(|v| !v.is_finite()) {
+                Some(vertex) => Err(ShapeError::NonFinite { vertex }),
+                None => Ok(()),
+            },
+            Shape::Polygon(sides) if *sides < 3 => Err(ShapeError::Polygon { sides: *sides }),
+            Shape::Torus(_, thickness) if !thickness.is_finite() => Err(ShapeError::Thickness),
+            Shape::Mesh(mesh, topology) => mesh.validate(*topology),
+            Shape::Instances(shape, _)
+                if matches!(
+                    **shape,
+                    Shape::Instances(..) | Shape::Line(..) | Shape::Path(..) | Shape::Point(..)
+                ) =>
+            {
+                Err(ShapeError::NotInstanceable)
+            }
+            Shape::Instances(shape, _) => shape.validate(),
+            Shape::Line(points, _, _) if points.len() < 2 => Err(ShapeError::TooFewPoints {
+                count: points.len(),
+            }),
+            Shape::Line(points, _, style) => {
+                if !style.width.is_valid() {
+                    return Err(ShapeError::Width);
+                }
+
+                match points.iter().position(|point| !point.is_finite()) {
+                    Some(vertex) => Err(ShapeError::NonFinite { vertex }),
+                    None => Ok(()),
+                }
+            }
+            Shape::Path(points, _) if points.len() < 2 => Err(ShapeError::TooFewPoints {
+                count: points.len(),
+            }),
+            Shape::Path(points, _) => match points.iter().position(|point| !point.is_finite()) {
+                Some(vertex) => Err(ShapeError::NonFinite { vertex }),
+                None => Ok(()),
+            },
+            Shape::Point(point) if !point.is_finite() => Err(ShapeError::NonFinite { vertex: 0 }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Identifies built-in shapes with the same geometry, `None` for shapes generated anew each
+    /// time.
+    pub(crate) fn geometry_key(&self) -> Option<GeometryKey> {
+        match *self {
+            Shape::Triangle(..)
+            | Shape::Mesh(..)
+            | Shape::Line(..)
+            | Shape::Path(..)
+            | Shape::Point(..) => None,
+            Shape::Square => Some(GeometryKey::Square),
+            Shape::Polygon(sides) => Some(GeometryKey::Polygon(sides)),
+            Shape::Cube => Some(GeometryKey::Cube),
+            Shape::Sphere(resolution) => Some(GeometryKey::Sphere(resolution)),
+            Shape::Icosphere(subdivisions) => Some(GeometryKey::Icosphere(subdivisions)),
+            Shape::Cylinder(resolution) => Some(GeometryKey::Cylinder(resolution)),
+            Shape::Cone(resolution) => Some(GeometryKey::Cone(resolution)),
+            Shape::Torus(resolution, thickness) => {
+                Some(GeometryKey::Torus(resolution, thickness.to_bits()))
+            }
+            Shape::Plane(resolution) => Some(GeometryKey::Plane(resolution)),
+            Shape::Instances(ref shape, _) => shape.geometry_key(),
+        }
+    }
+
+    /// Whether the shape is flat or a triangle mesh, the shapes with outlines.
+    pub(crate) fn has_outline(&self) -> bool {
+        match self {
+            Shape::Path(..) | Shape::Triangle(..) | Shape::Square | Shape::Polygon(_) => true,
+            Shape::Mesh(_, topology) => topology.primitive() == PrimitiveTopology::TriangleList,
+            _ => false,
+        }
+    }
+
+    /// Outlines of flat shapes and triangle meshes, `None` for shapes that have none.
+    pub(crate) fn outlines(&self, view: &View) -> Option<Vec<Outline>> {
+        match self {
+            Shape::Path(pixels, closed) => Some(vec![(view.pixels_to_world(pixels), *closed)]),
+            Shape::Triangle(a, b, c) => Some(vec![(vec![*a, *b, *c], true)]),
+            Shape::Square => Some(vec![geometry::square_outline()]),
+            Shape::Polygon(sides) => Some(vec![geometry::polygon_outline(*sides)]),
+            Shape::Mesh(_, topology) if topology.primitive() == PrimitiveTopology::TriangleList => {
+                Some(geometry::mesh_outline(&self.geometry()))
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn primitive(&self) -> PrimitiveTopology {
+        match self {
+            Shape::Mesh(_, topology) => topology.primitive(),
+            Shape::Instances(shape, _) => shape.primitive(),
+            _ => PrimitiveTopology::TriangleList,
+        }
+    }
+
+    pub(crate) fn geometry(&self) -> Geometry {
+        match self {
+            Shape::Triangle(a, b, c) => geometry::triangle(*a, *b, *c),
+            Shape::Square => geometry::square(),
+            Shape::Polygon(sides) => geometry::polygon(*sides),
+            Shape::Mesh(mesh, topology) => geometry::mesh(mesh, topology),
+            Shape::Cube => geometry::cube(),
+            Shape::Sphere(resolution) => geometry::sphere(*resolution),
+            Shape::Icosphere(subdivisions) => geometry::icosphere(*subdivisions),
+            Shape::Cylinder(resolution) => geometry::cylinder(*resolution),
+            Shape::Cone(resolution) => geometry::cone(*resolution),
+            Shape::Torus(resolution, thickness) => geometry::torus(*resolution, *thickness),
+            Shape::Plane(resolution) => geometry::plane(*resolution),
+            Shape::Instances(shape, _) => shape.geometry(),
+            // Depends on the view, built while serializing
+            Shape::Line(..) | Shape::Path(..) | Shape::Point(..) => (vec![], vec![]),
+        }
+    }
+}
+
+/// The parameters a built-in shape's geometry depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum GeometryKey {
+    Square,
+    Polygon(u32),
+    Cube,
+    Sphere(u32),
+    Icosphere(u32),
+    Cylinder(u32),
+    Cone(u32),
+    /// Thickness as bits, so the key can be hashed
+    Torus(u32, u32),
+    Plane(u32),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Topology {
     TriangleList,
     TriangleStrip,
+    TriangleFan,
+    LineList,
+    LineStrip,
+    PointList,
+}
+
+impl Topology {
+    /// The pipeline topology the mesh is drawn with once strips and fans are expanded.
+    pub(crate) fn primitive(&self) -> PrimitiveTopology {
+        match self {
+            Topology::TriangleList | Topology::TriangleStrip | Topology::TriangleFan => {
+                PrimitiveTopology::TriangleList
+            }
+            Topology::LineList | Topology::LineStrip => PrimitiveTopology::LineList,
+            Topology::PointList => PrimitiveTopology::PointList,
+        }
+    }
+
+    /// Vertices in one primitive.
+    pub(crate) fn vertices(&self) -> usize {
+        match self.primitive() {
+            PrimitiveTopology::TriangleList => 3,
+            PrimitiveTopology::LineList => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Lights in world space. A scene without lights is drawn unshaded with its flat colors.
+#[derive(Clone, Copy)]
+pub enum Light {
+    Ambient(Color),
+    Directional { direction: Vec3, color: Color },
+    Point { position: Vec3, color: Color },
 }
 
 #[derive(Default)]
@@ -195,10 +1184,35 @@ pub struct Camera {
     pub projection: CameraProjection,
 }
 
+impl Camera {
+    /// The view projection matrix, from world to clip space.
+    pub fn matrix(&self) -> Mat4 {
+        let view = Mat4::look_at_lh(self.view.eye, self.view.center, self.view.up);
+        let projection = match self.projection {
+            CameraProjection::Perspective {
+                fov_y_radians,
+                aspect_ratio,
+                z_near,
+                z_far,
+            } => Mat4::perspective_lh(fov_y_radians, aspect_ratio, z_near, z_far),
+            CameraProjection::Orthographic {
+                left,
+                right,
+                bottom,
+                top,
+                near,
+                far,
+            } => Mat4::orthographic_lh(left, right, bottom, top, near, far),
+        };
+
+        projection.mul_mat4(&view)
+    }
+}
+
 pub struct CameraView {
-    pub(crate) eye: Vec3,
-    pub(crate) center: Vec3,
-    pub(crate) up: Vec3,
+    pub eye: Vec3,
+    pub center: Vec3,
+    pub up: Vec3,
 }
 
 impl Default for CameraView {
@@ -246,6 +1260,42 @@ pub struct Transform {
     pub translation: Option<Vec3>,
 }
 
+impl Transform {
+    /// Scales, then rotates, then translates, each step applied in the space of the previous.
+    pub fn matrix(&self) -> Mat4 {
+        let mut matrix = Mat4::IDENTITY;
+
+        if let Some(scale) = self.scale {
+            matrix = matrix.mul_mat4(&Mat4::from_scale(scale));
+        };
+
+        if let Some(rotation) = self.rotation {
+            matrix = matrix.mul_mat4(&Mat4::from_quat(rotation));
+        };
+
+        if let Some(translation) = self.translation {
+            matrix = matrix.mul_mat4(&Mat4::from_translation(translation));
+        };
+
+        matrix
+    }
+
+    pub fn scaled(mut self, scale: [f32; 3]) -> Self {
+        self.scale = Some(Vec3::from_array(scale));
+        self
+    }
+
+    pub fn rotated(mut self, rotation: [f32; 4]) -> Self {
+        self.rotation = Some(Quat::from_array(rotation));
+        self
+    }
+
+    pub fn translated(mut self, translation: [f32; 3]) -> Self {
+        self.translation = Some(Vec3::from_array(translation));
+        self
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Frame {
     pub count: u32,
@@ -254,8 +1304,174 @@ pub struct Frame {
 
 #[derive(Default)]
 pub struct Node {
+    pub parent: Option<NodeId>,
     pub transform: Option<Transform>,
+    /// Fill color, `None` inherits the parent's.
     pub color: Option<Color>,
-    pub children: Vec<Rc<RefCell<Node>>>,
+    /// Whether flat shapes and triangle meshes are filled, `None` inherits the parent's.
+    /// Filled by default.
+    pub fill: Option<bool>,
+    /// Outline of flat shapes and triangle meshes, `None` inherits the parent's.
+    pub stroke: Option<Stroke>,
+    /// Width of the outlines, `None` inherits the parent's. One pixel by default.
+    pub stroke_width: Option<LineWidth>,
+    pub texture: Option<TextureId>,
+    /// Hidden nodes are not drawn, nor are their children
+    pub hidden: bool,
+    pub children: Vec<NodeId>,
     pub shapes: Vec<usize>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polygon_needs_three_sides() {
+        assert_eq!(
+            Shape::Polygon(2).validate(),
+            Err(ShapeError::Polygon { sides: 2 })
+        );
+        assert_eq!(Shape::Polygon(3).validate(), Ok(()));
+    }
+
+    #[test]
+    fn non_finite_shapes() {
+        let triangle = Shape::Triangle(Vec3::ZERO, Vec3::X, Vec3::splat(f32::NAN));
+        assert_eq!(
+            triangle.validate(),
+            Err(ShapeError::NonFinite { vertex: 2 })
+        );
+
+        assert_eq!(
+            Shape::Torus(8, f32::INFINITY).validate(),
+            Err(ShapeError::Thickness)
+        );
+    }
+
+    #[test]
+    fn instances_of_instances() {
+        let inner = Shape::Instances(Box::new(Shape::Square), vec![]);
+        let outer = Shape::Instances(Box::new(inner), vec![]);
+
+        assert_eq!(outer.validate(), Err(ShapeError::NotInstanceable));
+        assert_eq!(
+            Shape::Instances(Box::new(Shape::Polygon(1)), vec![]).validate(),
+            Err(ShapeError::Polygon { sides: 1 })
+        );
+    }
+
+    #[test]
+    fn line_needs_two_points_and_a_valid_width() {
+        let style = LineStyle::default();
+        assert_eq!(
+            Shape::Line(vec![Vec3::ZERO], false, style).validate(),
+            Err(ShapeError::TooFewPoints { count: 1 })
+        );
+
+        let style = LineStyle {
+            width: LineWidth::Screen(-1.),
+            ..style
+        };
+        assert_eq!(
+            Shape::Line(vec![Vec3::ZERO, Vec3::X], false, style).validate(),
+            Err(ShapeError::Width)
+        );
+    }
+
+    #[test]
+    fn try_serialize_fails_on_the_first_invalid_shape() {
+        let mut state = State::default();
+        state.clear_tree();
+        state.add_shape(NodeId::ROOT, Shape::Square);
+        state.add_shape(NodeId::ROOT, Shape::Polygon(0));
+
+        assert!(matches!(
+            state.try_serialize(),
+            Err(ShapeError::Polygon { sides: 0 })
+        ));
+    }
+
+    #[test]
+    fn instance_updates_match_a_rebuild() {
+        let mut state = State::default();
+        state.clear_tree();
+        let node = |transform: Transform, color: Option<Color>| Node {
+            transform: Some(transform),
+            color,
+            ..Default::default()
+        };
+
+        let parent = state.add_node(
+            NodeId::ROOT,
+            node(Transform::default(), Some([1., 0., 0., 1.].into())),
+        );
+        state.add_shape(parent, Shape::Cube);
+        let child = state.add_node(parent, node(Transform::default(), None));
+        state.add_shape(child, Shape::Square);
+        let copies = (0..3)
+            .map(|i| InstanceData {
+                transform: Transform::default().translated([i as f32, 0., 0.]),
+                opacity: Some(0.5),
+                ..Default::default()
+            })
+            .collect();
+        state.add_shape(child, Shape::Instances(Box::new(Shape::Cube), copies));
+        let sibling = state.add_node(NodeId::ROOT, node(Transform::default(), None));
+        state.add_shape(sibling, Shape::Polygon(5));
+        let mut generation = state.serialize().scene.generation;
+
+        let changes: [&dyn Fn(&mut State); 4] = [
+            &|state| {
+                let transform = Transform::default().translated([1., 2., 3.]);
+                state
+                    .update_instances(parent, true, |node| node.transform = Some(transform))
+                    .unwrap()
+            },
+            &|state| {
+                let color = Some([0., 1., 0., 1.].into());
+                state
+                    .update_instances(child, false, |node| node.color = color)
+                    .unwrap()
+            },
+            &|state| {
+                state
+                    .update_instances(parent, false, |node| node.hidden = true)
+                    .unwrap()
+            },
+            &|state| {
+                state
+                    .update_instances(parent, false, |node| node.hidden = false)
+                    .unwrap()
+            },
+        ];
+
+        for change in changes {
+            change(&mut state);
+            let updated = state.serialize();
+            assert_eq!(updated.scene.generation, generation);
+            assert!(!updated.instance_writes.is_empty());
+
+            state.dirty = true;
+            let rebuilt = state.serialize();
+            assert_eq!(
+                bytemuck::cast_slice::<Instance, u8>(&updated.scene.instances),
+                bytemuck::cast_slice::<Instance, u8>(&rebuilt.scene.instances),
+            );
+            generation = rebuilt.scene.generation;
+        }
+    }
+
+    #[test]
+    fn geometry_cache_keeps_only_drawn_shapes() {
+        let mut state = State::default();
+        for frame in 0..10 {
+            state.clear_tree();
+            state.add_shape(NodeId::ROOT, Shape::Torus(16, 0.1 + frame as f32));
+            state.add_shape(NodeId::ROOT, Shape::Cube);
+            state.serialize();
+        }
+
+        assert_eq!(state.geometry_cache.len(), 2);
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+/// Longest frame taken into account by the fixed timestep, so a stall doesn't queue up
+/// an unbounded number of fixed updates.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// How often the measured frame rate is refreshed.
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+pub struct Time {
+    /// Wall-clock time since the first frame.
+    pub elapsed: Duration,
+    /// Time between the last two frames.
+    pub delta: Duration,
+    /// Frames per second measured over the last second.
+    pub fps: f32,
+    /// How far the current frame is between the previous and next fixed update, in `[0, 1)`.
+    /// Only advances when running with a fixed timestep.
+    pub alpha: f32,
+    start: Instant,
+    last: Instant,
+    accumulator: Duration,
+    window_start: Instant,
+    window_frames: u32,
+}
+
+impl Time {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+
+        Time {
+            elapsed: Duration::ZERO,
+            delta: Duration::ZERO,
+            fps: 0.,
+            alpha: 0.,
+            start: now,
+            last: now,
+            accumulator: Duration::ZERO,
+            window_start: now,
+            window_frames: 0,
+        }
+    }
+
+    /// Advances the clock, called once at the beginning of each frame.
+    pub(crate) fn tick(&mut self) {
+        let now = Instant::now();
+
+        self.elapsed = now - self.start;
+        self.delta = now - self.last;
+        self.last = now;
+
+        self.window_frames += 1;
+        let window = now - self.window_start;
+        if window >= FPS_WINDOW {
+            self.fps = self.window_frames as f32 / window.as_secs_f32();
+            self.window_frames = 0;
+            self.window_start = now;
+        }
+    }
+
+    /// Adds the last frame to the time pending for fixed updates.
+    pub(crate) fn accumulate(&mut self) {
+        self.accumulator += self.delta.min(MAX_FRAME_TIME);
+    }
+
+    /// Consumes one fixed step from the time accumulated since the last frame, returns `false`
+    /// once there isn't enough left and updates `alpha` with the remainder.
+    pub(crate) fn fixed_step(&mut self, timestep: Duration) -> bool {
+        if self.accumulator >= timestep {
+            self.accumulator -= timestep;
+            true
+        } else {
+            self.alpha = self.accumulator.as_secs_f32() / timestep.as_secs_f32();
+            false
+        }
+    }
+}
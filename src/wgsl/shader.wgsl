@@ -1,16 +1,49 @@
-@group(0) @binding(0) var<uniform> camera : mat4x4<f32>;
+const MAX_LIGHTS: u32 = 8u;
+const SHININESS: f32 = 32.0;
+const SPECULAR: f32 = 0.5;
+
+struct Camera {
+    matrix: mat4x4<f32>,
+    eye: vec4<f32>,
+}
+
+struct Light {
+    // Direction for directional lights, position for point lights
+    vector: vec4<f32>,
+    color: vec4<f32>,
+}
+
+struct Lights {
+    ambient: vec4<f32>,
+    // Lighting enabled, directional and point light counts
+    counts: vec4<u32>,
+    directional: array<Light, MAX_LIGHTS>,
+    point: array<Light, MAX_LIGHTS>,
+}
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+@group(0) @binding(1) var<uniform> lights: Lights;
+
+@group(1) @binding(0) var color_texture: texture_2d<f32>;
+@group(1) @binding(1) var color_sampler: sampler;
 
 struct VertexInput {
     @location(0) position: vec4<f32>,
-    @location(1) mat_row_0: vec4<f32>,
-    @location(2) mat_row_1: vec4<f32>,
-    @location(3) mat_row_2: vec4<f32>,
-    @location(4) mat_row_3: vec4<f32>,
-    @location(5) color: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) mat_row_0: vec4<f32>,
+    @location(4) mat_row_1: vec4<f32>,
+    @location(5) mat_row_2: vec4<f32>,
+    @location(6) mat_row_3: vec4<f32>,
+    @location(7) color: vec4<f32>,
+    @location(8) vertex_color: vec4<f32>,
 }
 
 struct VertexOutput {
     @location(0) color: vec4<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) uv: vec2<f32>,
     @builtin(position) position: vec4<f32>,
 }
 
@@ -25,13 +58,70 @@ fn vs_main(input: VertexInput) -> VertexOutput {
         input.mat_row_3,
     );
 
-    output.color = input.color;
-    output.position = camera * transform * input.position;
+    let world_position = transform * input.position;
+
+    output.color = input.color * input.vertex_color;
+    output.uv = input.uv;
+    output.world_position = world_position.xyz;
+    // Exact for rotations and uniform scales, close enough for the rest
+    output.normal = (transform * vec4<f32>(input.normal.xyz, 0.0)).xyz;
+    output.position = camera.matrix * world_position;
 
     return output;
 }
 
+struct Shade {
+    diffuse: vec3<f32>,
+    specular: vec3<f32>,
+}
+
+// Blinn-Phong contribution of a light coming from `to_light`
+fn shade(normal: vec3<f32>, to_eye: vec3<f32>, to_light: vec3<f32>, color: vec3<f32>) -> Shade {
+    let diffuse = max(dot(normal, to_light), 0.0);
+    if diffuse <= 0.0 {
+        return Shade(vec3<f32>(0.0), vec3<f32>(0.0));
+    }
+
+    let half_vector = normalize(to_light + to_eye);
+    let specular = pow(max(dot(normal, half_vector), 0.0), SHININESS) * SPECULAR;
+
+    return Shade(color * diffuse, color * specular);
+}
+
 @fragment
 fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
-    return input.color;
+    let color = input.color * textureSample(color_texture, color_sampler, input.uv);
+
+    // Lines and points have no normal and are never lit
+    if lights.counts.x == 0u || all(input.normal == vec3<f32>(0.0)) {
+        return color;
+    }
+
+    let to_eye = normalize(camera.eye.xyz - input.world_position);
+
+    // Shapes have no inside or outside, light whichever side faces the camera
+    var normal = normalize(input.normal);
+    if dot(normal, to_eye) < 0.0 {
+        normal = -normal;
+    }
+
+    var diffuse = lights.ambient.rgb;
+    var specular = vec3<f32>(0.0);
+
+    for (var i = 0u; i < lights.counts.y; i++) {
+        let light = lights.directional[i];
+        let result = shade(normal, to_eye, -normalize(light.vector.xyz), light.color.rgb);
+        diffuse += result.diffuse;
+        specular += result.specular;
+    }
+
+    for (var i = 0u; i < lights.counts.z; i++) {
+        let light = lights.point[i];
+        let to_light = normalize(light.vector.xyz - input.world_position);
+        let result = shade(normal, to_eye, to_light, light.color.rgb);
+        diffuse += result.diffuse;
+        specular += result.specular;
+    }
+
+    return vec4<f32>(color.rgb * diffuse + specular, color.a);
 }